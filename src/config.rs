@@ -0,0 +1,654 @@
+use crate::hostmask::MatchMode;
+use crate::stoplist::Stoplist;
+use crate::tls::{TlsFiles, Verify};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+use thiserror::Error;
+use tokio_postgres::Config;
+
+/// Where to find the Quassel database.
+///
+/// Settings are layered, each overriding the last: built-in defaults
+/// (`host=localhost user=quassel dbname=quassel`), the selected profile from
+/// the config file, the `PG*` environment variables and finally these flags.
+/// A layer that sets a url replaces everything beneath it. The url flag can
+/// also come from `IDENTITY_TRAVERSE_DATABASE_URL`, which counts as the flag;
+/// the common `DATABASE_URL` is left alone, as it usually names some other
+/// project's database.
+#[derive(clap::Args, Debug, Default)]
+pub struct DatabaseArgs {
+    /// postgres url or key=value connection string
+    #[clap(long, env = "IDENTITY_TRAVERSE_DATABASE_URL")]
+    pub database_url: Option<String>,
+    /// named profile from the config file
+    #[clap(long)]
+    pub profile: Option<String>,
+    /// config file; defaults to $XDG_CONFIG_HOME/identity-traverse/config.toml
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+    /// file whose first line is the database password
+    #[clap(long)]
+    pub password_file: Option<PathBuf>,
+    /// whether to connect over TLS
+    #[clap(long, value_enum)]
+    pub ssl_mode: Option<SslMode>,
+    /// PEM bundle of CAs trusted to sign the server certificate
+    #[clap(long)]
+    pub ssl_root_cert: Option<PathBuf>,
+    /// PEM client certificate, for servers that require one
+    #[clap(long, requires = "ssl_key")]
+    pub ssl_cert: Option<PathBuf>,
+    /// PEM private key for --ssl-cert
+    #[clap(long, requires = "ssl_cert")]
+    pub ssl_key: Option<PathBuf>,
+}
+
+/// libpq's sslmodes: only the `verify-` ones check the server's certificate.
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    /// use TLS if the server supports it, taking any certificate
+    Prefer,
+    /// use TLS, taking any certificate
+    Require,
+    /// use TLS, with a certificate signed by a trusted CA
+    VerifyCa,
+    /// use TLS, with a certificate signed by a trusted CA for the host
+    VerifyFull,
+}
+
+impl SslMode {
+    fn verify(self) -> Verify {
+        match self {
+            SslMode::Disable | SslMode::Prefer | SslMode::Require => Verify::Nothing,
+            SslMode::VerifyCa => Verify::Ca,
+            SslMode::VerifyFull => Verify::Full,
+        }
+    }
+}
+
+impl From<SslMode> for tokio_postgres::config::SslMode {
+    fn from(mode: SslMode) -> Self {
+        match mode {
+            SslMode::Disable => Self::Disable,
+            SslMode::Prefer => Self::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => Self::Require,
+        }
+    }
+}
+
+/// Everything needed to open a connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    pub postgres: Config,
+    pub tls: TlsFiles,
+}
+
+/// The on-disk config file.
+///
+/// ```toml
+/// default = "home"
+///
+/// [profile.home]
+/// host = "localhost"
+///
+/// [profile.work]
+/// url = "postgres://quassel@db.example.net:5433/quassel"
+/// password_file = "~/.config/identity-traverse/work.pass"
+///
+/// [match]
+/// nick = "exact"
+/// host = "insensitive"
+///
+/// [stoplist]
+/// hosts = ["nat-*.campus.example.edu"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// profile used when `--profile` is not given
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+    #[serde(default, rename = "match")]
+    pub matching: MatchModes,
+    /// added to the built-in stoplist
+    #[serde(default)]
+    pub stoplist: Stoplist,
+}
+
+/// How each dimension matches, when the command line doesn't say.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchModes {
+    pub nick: Option<MatchMode>,
+    pub ident: Option<MatchMode>,
+    pub host: Option<MatchMode>,
+    pub realname: Option<MatchMode>,
+}
+
+impl MatchModes {
+    /// Layers `other` on top of `self`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            nick: other.nick.or(self.nick),
+            ident: other.ident.or(self.ident),
+            host: other.host.or(self.host),
+            realname: other.realname.or(self.realname),
+        }
+    }
+
+    /// Whether the seed's mask holds regexes. Realnames aren't part of it.
+    pub fn any_regex(&self) -> bool {
+        [self.nick, self.ident, self.host].contains(&Some(MatchMode::Regex))
+    }
+}
+
+impl ConfigFile {
+    /// The file `--config` names, else the one at the default path if there is
+    /// one, else an empty one.
+    pub fn find(args: &DatabaseArgs) -> Result<Self, ConfigError> {
+        match &args.config {
+            Some(path) => Self::load(path),
+            None => match Self::default_path().filter(|path| path.exists()) {
+                Some(path) => Self::load(&path),
+                None => Ok(Self::default()),
+            },
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e))?;
+        toml::from_str(&raw).map_err(|e| ConfigError::Parse(path.into(), e))
+    }
+
+    /// `$XDG_CONFIG_HOME/identity-traverse/config.toml`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("identity-traverse").join("config.toml"))
+    }
+}
+
+/// One layer of connection settings; unset fields fall through to the layer below.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub dbname: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<PathBuf>,
+    pub sslmode: Option<SslMode>,
+    pub sslrootcert: Option<PathBuf>,
+    pub sslcert: Option<PathBuf>,
+    pub sslkey: Option<PathBuf>,
+}
+
+impl Profile {
+    fn defaults() -> Self {
+        Self {
+            host: Some("localhost".into()),
+            user: Some("quassel".into()),
+            dbname: Some("quassel".into()),
+            ..Default::default()
+        }
+    }
+
+    /// libpq style `PGHOST`, `PGPORT`, `PGUSER`, `PGDATABASE`, `PGPASSWORD` and `PGSSL*`.
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        Ok(Self {
+            host: var("PGHOST"),
+            port: var("PGPORT")
+                .map(|port| port.parse().map_err(|_| ConfigError::Port(port)))
+                .transpose()?,
+            user: var("PGUSER"),
+            dbname: var("PGDATABASE"),
+            password: var("PGPASSWORD"),
+            sslmode: var("PGSSLMODE")
+                .map(|mode| SslMode::from_str(&mode, true).map_err(|_| ConfigError::SslMode(mode)))
+                .transpose()?,
+            sslrootcert: var("PGSSLROOTCERT").map(PathBuf::from),
+            sslcert: var("PGSSLCERT").map(PathBuf::from),
+            sslkey: var("PGSSLKEY").map(PathBuf::from),
+            ..Default::default()
+        })
+    }
+
+    fn from_args(args: &DatabaseArgs) -> Self {
+        Self {
+            url: args.database_url.clone(),
+            password_file: args.password_file.clone(),
+            sslmode: args.ssl_mode,
+            sslrootcert: args.ssl_root_cert.clone(),
+            sslcert: args.ssl_cert.clone(),
+            sslkey: args.ssl_key.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Layers `other` on top of `self`.
+    fn merge(self, other: Self) -> Self {
+        let base = if other.url.is_some() {
+            // urls can't carry certificate paths, so those survive
+            Self {
+                sslrootcert: self.sslrootcert,
+                sslcert: self.sslcert,
+                sslkey: self.sslkey,
+                ..Self::default()
+            }
+        } else {
+            self
+        };
+        // a password and a password file from the same layer fight over one slot
+        let (password, password_file) = if other.password.is_some() || other.password_file.is_some()
+        {
+            (other.password, other.password_file)
+        } else {
+            (base.password, base.password_file)
+        };
+        // likewise a client certificate is useless without its own key
+        let (sslcert, sslkey) = if other.sslcert.is_some() || other.sslkey.is_some() {
+            (other.sslcert, other.sslkey)
+        } else {
+            (base.sslcert, base.sslkey)
+        };
+        Self {
+            url: other.url.or(base.url),
+            host: other.host.or(base.host),
+            port: other.port.or(base.port),
+            user: other.user.or(base.user),
+            dbname: other.dbname.or(base.dbname),
+            password,
+            password_file,
+            sslmode: other.sslmode.or(base.sslmode),
+            sslrootcert: other.sslrootcert.or(base.sslrootcert),
+            sslcert,
+            sslkey,
+        }
+    }
+
+    fn into_config(self) -> Result<ConnectionConfig, ConfigError> {
+        let (mut config, url_sslmode) = match &self.url {
+            Some(url) => {
+                let (url, sslmode) = take_sslmode(url)?;
+                (url.parse().map_err(ConfigError::Url)?, sslmode)
+            }
+            None => (Config::new(), None),
+        };
+        if let Some(host) = &self.host {
+            config.host(host);
+        }
+        if let Some(port) = self.port {
+            config.port(port);
+        }
+        if let Some(user) = &self.user {
+            config.user(user);
+        }
+        if let Some(dbname) = &self.dbname {
+            config.dbname(dbname);
+        }
+        if let Some(password) = &self.password {
+            config.password(password);
+        } else if let Some(path) = &self.password_file {
+            config.password(read_password(path)?);
+        }
+        // like libpq, TLS is preferred unless some layer says otherwise
+        let sslmode = self.sslmode.or(url_sslmode).unwrap_or(SslMode::Prefer);
+        config.ssl_mode(sslmode.into());
+        Ok(ConnectionConfig {
+            postgres: config,
+            tls: TlsFiles {
+                verify: sslmode.verify(),
+                root_cert: self.sslrootcert,
+                cert: self.sslcert,
+                key: self.sslkey,
+            },
+        })
+    }
+}
+
+/// Takes the `sslmode` out of a url or key=value connection string, as
+/// tokio-postgres doesn't know the `verify-` ones.
+fn take_sslmode(url: &str) -> Result<(String, Option<SslMode>), ConfigError> {
+    let parse =
+        |mode: &str| SslMode::from_str(mode, true).map_err(|_| ConfigError::SslMode(mode.into()));
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        let Some((base, query)) = url.split_once('?') else {
+            return Ok((url.into(), None));
+        };
+        let mut sslmode = None;
+        let mut params = Vec::new();
+        for param in query.split('&') {
+            match param.strip_prefix("sslmode=") {
+                Some(mode) => sslmode = Some(parse(mode)?),
+                None => params.push(param),
+            }
+        }
+        let url = match params.is_empty() {
+            true => base.to_string(),
+            false => format!("{base}?{}", params.join("&")),
+        };
+        return Ok((url, sslmode));
+    }
+    let mut sslmode = None;
+    let mut pairs = Vec::new();
+    for pair in url.split_whitespace() {
+        match pair.strip_prefix("sslmode=") {
+            Some(mode) => sslmode = Some(parse(mode)?),
+            None => pairs.push(pair),
+        }
+    }
+    Ok((pairs.join(" "), sslmode))
+}
+
+/// Resolves `args` against the config file and environment into a connection config.
+pub fn resolve(args: &DatabaseArgs, file: &ConfigFile) -> Result<ConnectionConfig, ConfigError> {
+    resolve_with(args, file, |key| env::var(key).ok())
+}
+
+fn resolve_with(
+    args: &DatabaseArgs,
+    file: &ConfigFile,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<ConnectionConfig, ConfigError> {
+    let profile = match args.profile.as_ref().or(file.default.as_ref()) {
+        Some(name) => file
+            .profile
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ConfigError::UnknownProfile(name.clone()))?,
+        None => Profile::default(),
+    };
+    Profile::defaults()
+        .merge(profile)
+        .merge(Profile::from_env(var)?)
+        .merge(Profile::from_args(args))
+        .into_config()
+}
+
+fn read_password(path: &Path) -> Result<String, ConfigError> {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(rest))
+            .unwrap_or_else(|| path.into()),
+        Err(_) => path.into(),
+    };
+    let raw = fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+    Ok(raw.lines().next().unwrap_or_default().to_string())
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("cannot parse {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("no profile named {0:?} in the config file")]
+    UnknownProfile(String),
+    #[error("invalid port {0:?}")]
+    Port(String),
+    #[error("invalid sslmode {0:?}")]
+    SslMode(String),
+    #[error("invalid database url: {0}")]
+    Url(tokio_postgres::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{resolve_with, ConfigFile, DatabaseArgs, MatchModes};
+    use crate::hostmask::MatchMode;
+    use crate::tls::{TlsFiles, Verify};
+    use clap::Args;
+    use std::collections::HashMap;
+    use std::fs;
+    use tokio_postgres::config::{Host, SslMode};
+
+    const FILE: &str = r#"
+        default = "home"
+
+        [profile.home]
+        host = "quassel.lan"
+        password = "hunter2"
+
+        [profile.work]
+        url = "postgres://core@db.example.net:5433/backlog"
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn host(config: &tokio_postgres::Config) -> String {
+        match &config.get_hosts()[0] {
+            Host::Tcp(host) => host.clone(),
+            Host::Unix(path) => path.display().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = resolve_with(&DatabaseArgs::default(), &ConfigFile::default(), env(&[]))
+            .unwrap()
+            .postgres;
+        assert_eq!(host(&config), "localhost");
+        assert_eq!(config.get_user(), Some("quassel"));
+        assert_eq!(config.get_dbname(), Some("quassel"));
+        assert_eq!(config.get_password(), None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+
+        let config = resolve_with(&DatabaseArgs::default(), &file, env(&[]))
+            .unwrap()
+            .postgres;
+        assert_eq!(host(&config), "quassel.lan");
+        assert_eq!(config.get_user(), Some("quassel"));
+        assert_eq!(config.get_password(), Some(&b"hunter2"[..]));
+
+        let args = DatabaseArgs {
+            profile: Some("work".into()),
+            ..Default::default()
+        };
+        let config = resolve_with(&args, &file, env(&[])).unwrap().postgres;
+        assert_eq!(host(&config), "db.example.net");
+        assert_eq!(config.get_ports(), [5433]);
+        assert_eq!(config.get_user(), Some("core"));
+        assert_eq!(config.get_dbname(), Some("backlog"));
+
+        let args = DatabaseArgs {
+            profile: Some("missing".into()),
+            ..Default::default()
+        };
+        assert!(resolve_with(&args, &file, env(&[])).is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+
+        let vars = env(&[
+            ("PGHOST", "env.lan"),
+            ("PGPORT", "6543"),
+            ("PGUSER", "envuser"),
+        ]);
+        let config = resolve_with(&DatabaseArgs::default(), &file, vars)
+            .unwrap()
+            .postgres;
+        assert_eq!(host(&config), "env.lan");
+        assert_eq!(config.get_ports(), [6543]);
+        assert_eq!(config.get_user(), Some("envuser"));
+        // untouched by the environment, so still from the profile
+        assert_eq!(config.get_password(), Some(&b"hunter2"[..]));
+
+        let args = DatabaseArgs {
+            database_url: Some("host=cli.lan user=cli".into()),
+            ..Default::default()
+        };
+        let config = resolve_with(&args, &file, env(&[("PGHOST", "env.lan")]))
+            .unwrap()
+            .postgres;
+        assert_eq!(host(&config), "cli.lan");
+        assert_eq!(config.get_user(), Some("cli"));
+        assert_eq!(config.get_dbname(), None);
+        assert_eq!(config.get_password(), None);
+
+        assert!(resolve_with(&args, &file, env(&[("PGPORT", "nope")])).is_err());
+
+        // only our own variable stands in for the flag
+        let command = DatabaseArgs::augment_args(clap::Command::new("test"));
+        let url = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "database_url")
+            .unwrap();
+        assert_eq!(
+            url.get_env().and_then(|env| env.to_str()),
+            Some("IDENTITY_TRAVERSE_DATABASE_URL")
+        );
+    }
+
+    #[test]
+    fn test_ssl() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [profile.remote]
+            host = "db.example.net"
+            sslmode = "verify-full"
+            sslrootcert = "/etc/ssl/quassel-ca.pem"
+            "#,
+        )
+        .unwrap();
+
+        let config = resolve_with(&DatabaseArgs::default(), &file, env(&[])).unwrap();
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Prefer);
+        assert_eq!(config.tls, TlsFiles::default());
+        let config = resolve_with(&DatabaseArgs::default(), &file, env(&[("PGHOST", "db")]));
+        assert_eq!(config.unwrap().postgres.get_ssl_mode(), SslMode::Prefer);
+
+        let args = DatabaseArgs {
+            profile: Some("remote".into()),
+            ..Default::default()
+        };
+        let config = resolve_with(&args, &file, env(&[])).unwrap();
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Require);
+        assert_eq!(config.tls.verify, Verify::Full);
+        assert_eq!(config.tls.root_cert, Some("/etc/ssl/quassel-ca.pem".into()));
+
+        let vars = env(&[
+            ("PGSSLMODE", "prefer"),
+            ("PGSSLCERT", "c.pem"),
+            ("PGSSLKEY", "k.pem"),
+        ]);
+        let config = resolve_with(&args, &file, vars).unwrap();
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Prefer);
+        assert_eq!(config.tls.verify, Verify::Nothing);
+        assert_eq!(config.tls.cert, Some("c.pem".into()));
+        assert_eq!(config.tls.key, Some("k.pem".into()));
+
+        // a url brings its own sslmode but keeps the certificate paths
+        let args = DatabaseArgs {
+            profile: Some("remote".into()),
+            database_url: Some(
+                "postgres://quassel@other.example.net/quassel?sslmode=require".into(),
+            ),
+            ..Default::default()
+        };
+        let config = resolve_with(&args, &file, env(&[])).unwrap();
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Require);
+        assert_eq!(config.tls.verify, Verify::Nothing);
+        assert_eq!(config.tls.root_cert, Some("/etc/ssl/quassel-ca.pem".into()));
+
+        assert!(resolve_with(&args, &file, env(&[("PGSSLMODE", "sometimes")])).is_err());
+    }
+
+    #[test]
+    fn test_url_sslmode() {
+        let file = ConfigFile::default();
+        let url = |url: &str| {
+            let args = DatabaseArgs {
+                database_url: Some(url.into()),
+                ..Default::default()
+            };
+            resolve_with(&args, &file, env(&[])).unwrap()
+        };
+        // like libpq, a url without an sslmode tries TLS but takes any certificate
+        let config = url("postgres://quassel@db.example.net/quassel");
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Prefer);
+        assert_eq!(config.tls.verify, Verify::Nothing);
+
+        let config =
+            url("postgres://quassel@db.example.net/quassel?sslmode=verify-ca&application_name=it");
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Require);
+        assert_eq!(config.tls.verify, Verify::Ca);
+        assert_eq!(config.postgres.get_application_name(), Some("it"));
+
+        let config = url("host=db.example.net sslmode=verify-full user=quassel");
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Require);
+        assert_eq!(config.tls.verify, Verify::Full);
+        assert_eq!(config.postgres.get_user(), Some("quassel"));
+
+        let config = url("host=localhost sslmode=disable");
+        assert_eq!(config.postgres.get_ssl_mode(), SslMode::Disable);
+
+        let args = DatabaseArgs {
+            database_url: Some("postgres://db.example.net/quassel?sslmode=maybe".into()),
+            ..Default::default()
+        };
+        assert!(resolve_with(&args, &file, env(&[])).is_err());
+    }
+
+    #[test]
+    fn test_password_file() {
+        let path =
+            std::env::temp_dir().join(format!("identity-traverse-{}.pass", std::process::id()));
+        fs::write(&path, "s3cret\n").unwrap();
+        let args = DatabaseArgs {
+            password_file: Some(path.clone()),
+            ..Default::default()
+        };
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+        let config = resolve_with(&args, &file, env(&[])).unwrap().postgres;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.get_password(), Some(&b"s3cret"[..]));
+    }
+
+    #[test]
+    fn test_match() {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+        assert_eq!(file.matching, MatchModes::default());
+
+        let file: ConfigFile = toml::from_str(
+            r#"
+            [match]
+            nick = "exact"
+            host = "case-insensitive"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            file.matching,
+            MatchModes {
+                nick: Some(MatchMode::Exact),
+                ident: None,
+                host: Some(MatchMode::Insensitive),
+                realname: None,
+            }
+        );
+        assert!(!file.matching.any_regex());
+        assert!(toml::from_str::<ConfigFile>("[match]\nnick = \"fuzzy\"").is_err());
+        assert!(toml::from_str::<ConfigFile>("[match]\nchannel = \"exact\"").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Just enough calendar arithmetic to read and print UTC dates without
+//! pulling in a date crate.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Parses a `YYYY-MM-DD` date as midnight UTC.
+pub fn parse(s: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("{s:?} is not a YYYY-MM-DD date");
+    let mut parts = s.splitn(3, '-');
+    let mut next = |len: usize| {
+        parts
+            .next()
+            .filter(|part| part.len() == len && part.chars().all(|ch| ch.is_ascii_digit()))
+            .and_then(|part| part.parse::<u32>().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next(4)?, next(2)?, next(2)?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return Err(format!("{s:?} is before 1970"));
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(days as u64 * DAY))
+}
+
+/// The day after `time`'s, at midnight UTC.
+pub fn next_day(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs((secs / DAY + 1) * DAY)
+}
+
+/// `YYYY-MM-DD`, in UTC.
+pub fn date(time: SystemTime) -> String {
+    let (year, month, day, _) = civil(time);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// RFC 3339, e.g. `2024-06-01T08:00:00Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let (year, month, day, secs) = civil(time);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The year, month, day and seconds into the day of `time`, clamped to the epoch.
+fn civil(time: SystemTime) -> (i64, u32, u32, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / DAY) as i64);
+    (year, month, day, secs % DAY)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01, after Howard Hinnant's `days_from_civil`.
+fn days_from_civil(year: u32, month: u32, day: u32) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::date::{date, next_day, parse, timestamp};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(parse("1970-01-01"), Ok(UNIX_EPOCH));
+        assert_eq!(
+            parse("2024-06-01"),
+            Ok(UNIX_EPOCH + Duration::from_secs(1_717_200_000))
+        );
+        for s in ["2000-02-29", "2024-12-31", "2015-03-01"] {
+            assert_eq!(date(parse(s).unwrap()), s);
+        }
+        for s in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-6-1",
+            "2024-06-01T00:00",
+            "1969-12-31",
+            "",
+        ] {
+            assert!(parse(s).is_err(), "{s}");
+        }
+        let time = parse("2024-02-29").unwrap() + Duration::from_secs(3723);
+        assert_eq!(timestamp(time), "2024-02-29T01:02:03Z");
+        assert_eq!(next_day(time), parse("2024-03-01").unwrap());
+    }
+}
@@ -0,0 +1,488 @@
+use crate::date;
+use crate::graph::{Graph, NodeId};
+use crate::traversal::Sender;
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Formats a traversal [`Graph`] can be written out in.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// graphviz digraph
+    Dot,
+    /// GraphML, for gephi, yEd and friends
+    Graphml,
+    /// `{"nodes": [...], "edges": [...]}`
+    Json,
+    /// one row per edge
+    Csv,
+}
+
+/// Writes every node and edge of `graph` to `out` as `format`.
+pub fn write(graph: &Graph, format: Format, out: &mut impl Write) -> io::Result<()> {
+    match format {
+        Format::Dot => dot(graph, out),
+        Format::Graphml => graphml(graph, out),
+        Format::Json => json(graph, out),
+        Format::Csv => csv(graph, out),
+    }
+}
+
+fn dot(graph: &Graph, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "digraph identities {{")?;
+    for (id, sender) in graph.nodes() {
+        let shape = if id == Graph::SEED {
+            ", shape=doublecircle"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  n{id} [label=\"{}\"{shape}];",
+            quoted(&sender.sender.to_string())
+        )?;
+    }
+    for edge in graph.edges() {
+        // observed links are bold, weak ones dashed and cross-network ones
+        // dotted; dot's own `weight` has to be an integer
+        let style = if edge.observed.is_some() {
+            ", style=bold"
+        } else if edge.cross_network {
+            ", style=dotted"
+        } else if edge.weight < 1.0 {
+            ", style=dashed"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "  n{} -> n{} [label=\"{} {}\", depth={}{style}];",
+            edge.from,
+            edge.to,
+            edge.dimension,
+            quoted(&edge.pattern),
+            edge.depth
+        )?;
+    }
+    writeln!(out, "}}")
+}
+
+/// Escapes a DOT double-quoted string.
+fn quoted(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn graphml(graph: &Graph, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for (key, on, kind) in [
+        ("mask", "node", "string"),
+        ("nick", "node", "string"),
+        ("ident", "node", "string"),
+        ("host", "node", "string"),
+        ("realname", "node", "string"),
+        ("avatar_url", "node", "string"),
+        ("account", "node", "string"),
+        ("first_seen", "node", "string"),
+        ("last_seen", "node", "string"),
+        ("messages", "node", "long"),
+        ("networks", "node", "string"),
+        ("channels", "node", "string"),
+        ("seed", "node", "boolean"),
+        ("dimension", "edge", "string"),
+        ("pattern", "edge", "string"),
+        ("depth", "edge", "int"),
+        ("weight", "edge", "double"),
+        ("cross_network", "edge", "boolean"),
+        ("observed", "edge", "string"),
+    ] {
+        writeln!(
+            out,
+            r#"  <key id="{key}" for="{on}" attr.name="{key}" attr.type="{kind}"/>"#
+        )?;
+    }
+    writeln!(out, r#"  <graph id="identities" edgedefault="directed">"#)?;
+    for (id, sender) in graph.nodes() {
+        writeln!(out, r#"    <node id="n{id}">"#)?;
+        let mask = &sender.sender;
+        let activity = sender.activity.as_ref();
+        let data = [
+            ("mask", Some(mask.to_string())),
+            ("nick", Some(mask.nick().to_string())),
+            ("ident", Some(mask.ident().to_string())),
+            ("host", Some(mask.host().to_string())),
+            ("realname", sender.realname.clone()),
+            ("avatar_url", sender.avatar.clone()),
+            (
+                "account",
+                mask.host().account().map(|account| account.to_string()),
+            ),
+            (
+                "first_seen",
+                activity.map(|activity| date::timestamp(activity.first_seen)),
+            ),
+            (
+                "last_seen",
+                activity.map(|activity| date::timestamp(activity.last_seen)),
+            ),
+            (
+                "messages",
+                activity.map(|activity| activity.messages.to_string()),
+            ),
+            (
+                "networks",
+                (!sender.networks.is_empty()).then(|| sender.networks.join(" ")),
+            ),
+            (
+                "channels",
+                (!sender.channels.is_empty()).then(|| sender.channels.iter().join(" ")),
+            ),
+            ("seed", Some((id == Graph::SEED).to_string())),
+        ];
+        for (key, value) in data {
+            if let Some(value) = value {
+                writeln!(out, r#"      <data key="{key}">{}</data>"#, escaped(&value))?;
+            }
+        }
+        writeln!(out, "    </node>")?;
+    }
+    for (i, edge) in graph.edges().iter().enumerate() {
+        writeln!(
+            out,
+            r#"    <edge id="e{i}" source="n{}" target="n{}">"#,
+            edge.from, edge.to
+        )?;
+        writeln!(
+            out,
+            r#"      <data key="dimension">{}</data>"#,
+            edge.dimension
+        )?;
+        writeln!(
+            out,
+            r#"      <data key="pattern">{}</data>"#,
+            escaped(&edge.pattern)
+        )?;
+        writeln!(out, r#"      <data key="depth">{}</data>"#, edge.depth)?;
+        writeln!(out, r#"      <data key="weight">{}</data>"#, edge.weight)?;
+        writeln!(
+            out,
+            r#"      <data key="cross_network">{}</data>"#,
+            edge.cross_network
+        )?;
+        if let Some(observed) = edge.observed {
+            writeln!(
+                out,
+                r#"      <data key="observed">{}</data>"#,
+                date::timestamp(observed)
+            )?;
+        }
+        writeln!(out, "    </edge>")?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+/// Escapes XML character data and attribute values. XML 1.0 can't hold C0
+/// controls other than tab and newlines even escaped, so mIRC colour and
+/// formatting codes in realnames become U+FFFD.
+fn escaped(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(ch),
+            '\0'..='\x1f' => escaped.push(char::REPLACEMENT_CHARACTER),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonNode<'a> {
+    id: NodeId,
+    /// `senderid` in the core, absent for the seed
+    sender_id: Option<i64>,
+    mask: String,
+    nick: String,
+    ident: String,
+    /// whether identd vouched for the ident
+    ident_verified: bool,
+    host: String,
+    realname: Option<&'a str>,
+    avatar_url: Option<&'a str>,
+    /// the services account the host is a cloak of
+    account: Option<String>,
+    /// RFC 3339, absent with the rest of the activity when the backlog has
+    /// nothing from the sender
+    first_seen: Option<String>,
+    last_seen: Option<String>,
+    messages: Option<i64>,
+    /// every network the backlog has the sender on
+    networks: &'a [String],
+    /// `network/#channel`, for every channel the backlog has the sender in
+    channels: Vec<String>,
+    seed: bool,
+}
+
+impl<'a> JsonNode<'a> {
+    pub fn new(id: NodeId, sender: &'a Sender) -> Self {
+        let mask = &sender.sender;
+        let activity = sender.activity.as_ref();
+        Self {
+            id,
+            sender_id: sender.id,
+            mask: mask.to_string(),
+            nick: mask.nick().to_string(),
+            ident: mask.ident().to_string(),
+            ident_verified: mask.ident().verified(),
+            host: mask.host().to_string(),
+            realname: sender.realname.as_deref(),
+            avatar_url: sender.avatar.as_deref(),
+            account: mask.host().account().map(|account| account.to_string()),
+            first_seen: activity.map(|activity| date::timestamp(activity.first_seen)),
+            last_seen: activity.map(|activity| date::timestamp(activity.last_seen)),
+            messages: activity.map(|activity| activity.messages),
+            networks: &sender.networks,
+            channels: sender.channels.iter().map(|c| c.to_string()).collect(),
+            seed: id == Graph::SEED,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: NodeId,
+    to: NodeId,
+    dimension: &'static str,
+    pattern: &'a str,
+    depth: usize,
+    weight: f32,
+    cross_network: bool,
+    /// RFC 3339, for links the backlog saw made, like nick changes
+    observed: Option<String>,
+}
+
+fn json(graph: &Graph, out: &mut impl Write) -> io::Result<()> {
+    let graph = JsonGraph {
+        nodes: graph
+            .nodes()
+            .map(|(id, sender)| JsonNode::new(id, sender))
+            .collect(),
+        edges: graph
+            .edges()
+            .iter()
+            .map(|edge| JsonEdge {
+                from: edge.from,
+                to: edge.to,
+                dimension: edge.dimension,
+                pattern: &edge.pattern,
+                depth: edge.depth,
+                weight: edge.weight,
+                cross_network: edge.cross_network,
+                observed: edge.observed.map(date::timestamp),
+            })
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *out, &graph)?;
+    writeln!(out)
+}
+
+fn csv(graph: &Graph, out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "from,to,dimension,pattern,depth,weight,cross_network,observed"
+    )?;
+    for edge in graph.edges() {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            field(&graph.sender(edge.from).sender.to_string()),
+            field(&graph.sender(edge.to).sender.to_string()),
+            edge.dimension,
+            field(&edge.pattern),
+            edge.depth,
+            edge.weight,
+            edge.cross_network,
+            edge.observed.map(date::timestamp).unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field when it needs it, per RFC 4180.
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::date;
+    use crate::export::{write, Format};
+    use crate::graph::{Edge, Graph};
+    use crate::hostmask::HostMask;
+    use crate::traversal::{Activity, Channel, Sender, NICK_CHANGE};
+    use std::str::FromStr;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new(Sender::seed(
+            HostMask::from_str("kks!~kks@user/kks").unwrap(),
+        ));
+        let (a, _) = graph.insert(Sender {
+            id: Some(2),
+            sender: HostMask::from_str("kks_!~kks@user/kks").unwrap(),
+            realname: Some("<kks> & \"friends\"".to_string()),
+            avatar: Some("https://example.com/kks.png".to_string()),
+            activity: Some(Activity {
+                first_seen: date::parse("2015-03-01").unwrap(),
+                last_seen: date::parse("2024-06-01").unwrap(),
+                messages: 3,
+            }),
+            networks: vec!["libera".to_string()],
+            channels: vec![Channel {
+                network: "libera".to_string(),
+                name: "#quassel".to_string(),
+            }],
+        });
+        let (b, _) = graph.insert(Sender {
+            realname: Some("\x0304b\x03\tblue".to_string()),
+            ..Sender::stored(3, "b!~kks@example.org")
+        });
+        graph.link(Edge {
+            from: Graph::SEED,
+            to: a,
+            dimension: "nick",
+            pattern: "kks%".to_string(),
+            depth: 1,
+            weight: 1.0,
+            cross_network: false,
+            observed: None,
+        });
+        graph.link(Edge {
+            from: a,
+            to: b,
+            dimension: "ident",
+            pattern: "%~kks,%".to_string(),
+            depth: 2,
+            weight: 0.5,
+            cross_network: false,
+            observed: None,
+        });
+        graph.link(Edge {
+            from: Graph::SEED,
+            to: b,
+            dimension: "nick",
+            pattern: "b%".to_string(),
+            depth: 1,
+            weight: 0.5,
+            cross_network: true,
+            observed: None,
+        });
+        graph.link(Edge {
+            from: Graph::SEED,
+            to: a,
+            dimension: NICK_CHANGE,
+            pattern: "kks!~kks@user/kks".to_string(),
+            depth: 1,
+            weight: 1.0,
+            cross_network: false,
+            observed: Some(date::parse("2015-03-01").unwrap()),
+        });
+        graph
+    }
+
+    fn export(format: Format) -> String {
+        let mut out = Vec::new();
+        write(&graph(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = export(Format::Dot);
+        assert!(dot.starts_with("digraph identities {\n"));
+        assert!(dot.contains("  n0 [label=\"kks!~kks@user/kks\", shape=doublecircle];\n"));
+        assert!(dot.contains("  n0 -> n1 [label=\"nick kks%\", depth=1];\n"));
+        assert!(dot.contains("  n1 -> n2 [label=\"ident %~kks,%\", depth=2, style=dashed];\n"));
+        assert!(dot.contains("  n0 -> n2 [label=\"nick b%\", depth=1, style=dotted];\n"));
+        assert!(dot.contains(
+            "  n0 -> n1 [label=\"nick change kks!~kks@user/kks\", depth=1, style=bold];\n"
+        ));
+    }
+
+    #[test]
+    fn test_graphml() {
+        let graphml = export(Format::Graphml);
+        assert!(graphml
+            .contains(r#"<data key="realname">&lt;kks&gt; &amp; &quot;friends&quot;</data>"#));
+        assert!(graphml.contains("<data key=\"realname\">\u{fffd}04b\u{fffd}\tblue</data>"));
+        assert!(graphml.contains(r#"<edge id="e0" source="n0" target="n1">"#));
+        assert_eq!(graphml.matches("<node ").count(), 3);
+        assert!(graphml.contains(r#"<data key="networks">libera</data>"#));
+        assert_eq!(
+            graphml
+                .matches(r#"<data key="cross_network">true</data>"#)
+                .count(),
+            1
+        );
+        assert!(graphml.contains(r#"<data key="observed">2015-03-01T00:00:00Z</data>"#));
+    }
+
+    #[test]
+    fn test_json() {
+        let json: serde_json::Value = serde_json::from_str(&export(Format::Json)).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(json["nodes"][0]["seed"], true);
+        assert_eq!(json["nodes"][0]["sender_id"], serde_json::Value::Null);
+        assert_eq!(json["nodes"][1]["realname"], "<kks> & \"friends\"");
+        assert_eq!(json["nodes"][1]["ident_verified"], false);
+        assert_eq!(
+            json["nodes"][1]["avatar_url"],
+            "https://example.com/kks.png"
+        );
+        assert_eq!(json["nodes"][1]["account"], "kks");
+        assert_eq!(json["nodes"][2]["account"], serde_json::Value::Null);
+        assert_eq!(json["nodes"][1]["first_seen"], "2015-03-01T00:00:00Z");
+        assert_eq!(json["nodes"][1]["messages"], 3);
+        assert_eq!(json["nodes"][1]["channels"][0], "libera/#quassel");
+        assert_eq!(json["nodes"][2]["last_seen"], serde_json::Value::Null);
+        assert_eq!(json["edges"][1]["dimension"], "ident");
+        assert_eq!(json["edges"][1]["depth"], 2);
+        assert_eq!(json["edges"][1]["weight"], 0.5);
+        assert_eq!(json["edges"][1]["cross_network"], false);
+        assert_eq!(json["edges"][2]["cross_network"], true);
+        assert_eq!(json["edges"][2]["observed"], serde_json::Value::Null);
+        assert_eq!(json["edges"][3]["observed"], "2015-03-01T00:00:00Z");
+        assert_eq!(json["nodes"][1]["networks"][0], "libera");
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            export(Format::Csv),
+            "from,to,dimension,pattern,depth,weight,cross_network,observed\n\
+             kks!~kks@user/kks,kks_!~kks@user/kks,nick,kks%,1,1,false,\n\
+             kks_!~kks@user/kks,b!~kks@example.org,ident,\"%~kks,%\",2,0.5,false,\n\
+             kks!~kks@user/kks,b!~kks@example.org,nick,b%,1,0.5,true,\n\
+             kks!~kks@user/kks,kks_!~kks@user/kks,nick change,kks!~kks@user/kks,1,1,false,\
+             2015-03-01T00:00:00Z\n"
+        );
+    }
+}
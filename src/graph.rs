@@ -0,0 +1,216 @@
+use crate::traversal::Sender;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::time::SystemTime;
+
+/// Index of a sender within a [`Graph`].
+pub type NodeId = usize;
+
+/// A link between two senders: querying `pattern` on `dimension`, built
+/// from `from`, turned up `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub dimension: &'static str,
+    pub pattern: String,
+    /// iteration the query ran in, counting from 1
+    pub depth: usize,
+    /// how strongly the link suggests one person, from 0 to 1
+    pub weight: f32,
+    /// the two senders were only ever seen on different networks, which
+    /// makes for a weaker kind of link
+    pub cross_network: bool,
+    /// when the backlog saw the link made, for an observed nick change
+    pub observed: Option<SystemTime>,
+}
+
+/// A query term the traversal refused to pivot through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suppressed {
+    pub dimension: &'static str,
+    pub term: String,
+    pub reason: Suppression,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suppression {
+    /// on the stoplist
+    Stoplist,
+    /// turned up more senders than this, the fan-out limit
+    Hub(usize),
+}
+
+impl Display for Suppression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Suppression::Stoplist => f.write_str("on the stoplist"),
+            Suppression::Hub(limit) => write!(f, "hub of over {limit} senders"),
+        }
+    }
+}
+
+/// Senders discovered by a traversal and the links between them.
+///
+/// Node 0 is always the seed. Every other node has at least one incoming
+/// edge, the first of which is how it was discovered.
+#[derive(Debug, Clone)]
+pub struct Graph {
+    senders: Vec<Sender>,
+    index: HashMap<Sender, NodeId>,
+    edges: Vec<Edge>,
+    links: HashSet<(NodeId, NodeId, &'static str)>,
+    /// per node, the edge that discovered it
+    found_by: Vec<Option<usize>>,
+    suppressed: Vec<Suppressed>,
+}
+
+impl Graph {
+    pub const SEED: NodeId = 0;
+
+    pub fn new(seed: Sender) -> Self {
+        Self {
+            index: HashMap::from([(seed.clone(), Self::SEED)]),
+            senders: vec![seed],
+            edges: Vec::new(),
+            links: HashSet::new(),
+            found_by: vec![None],
+            suppressed: Vec::new(),
+        }
+    }
+
+    /// Adds `sender` if it is new, returning its id and whether it was new.
+    pub fn insert(&mut self, sender: Sender) -> (NodeId, bool) {
+        if let Some(&id) = self.index.get(&sender) {
+            return (id, false);
+        }
+        let id = self.senders.len();
+        self.index.insert(sender.clone(), id);
+        self.senders.push(sender);
+        self.found_by.push(None);
+        (id, true)
+    }
+
+    /// The node `sender` was inserted as, if it has been.
+    pub fn find(&self, sender: &Sender) -> Option<NodeId> {
+        self.index.get(sender).copied()
+    }
+
+    /// Records `edge`, ignoring self links and links we already have.
+    pub fn link(&mut self, edge: Edge) {
+        if edge.from == edge.to || !self.links.insert((edge.from, edge.to, edge.dimension)) {
+            return;
+        }
+        if self.found_by[edge.to].is_none() && edge.to != Self::SEED {
+            self.found_by[edge.to] = Some(self.edges.len());
+        }
+        self.edges.push(edge);
+    }
+
+    pub fn suppress(&mut self, suppressed: Suppressed) {
+        self.suppressed.push(suppressed);
+    }
+
+    /// Terms left unexplored, in the order they were suppressed.
+    pub fn suppressed(&self) -> &[Suppressed] {
+        &self.suppressed
+    }
+
+    pub fn sender(&self, id: NodeId) -> &Sender {
+        &self.senders[id]
+    }
+
+    /// Every node, the seed included.
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Sender)> {
+        self.senders.iter().enumerate()
+    }
+
+    /// Every discovered sender, the seed excluded.
+    pub fn discovered(&self) -> impl Iterator<Item = (NodeId, &Sender)> {
+        self.nodes().skip(1)
+    }
+
+    /// Number of discovered senders, not counting the seed.
+    pub fn len(&self) -> usize {
+        self.senders.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// The edge that discovered `id`; `None` for the seed.
+    pub fn found_by(&self, id: NodeId) -> Option<&Edge> {
+        self.found_by[id].map(|edge| &self.edges[edge])
+    }
+
+    /// Discovery edges leading from the seed to `id`, seed side first.
+    pub fn chain(&self, id: NodeId) -> Vec<&Edge> {
+        let mut chain = Vec::new();
+        let mut node = id;
+        while let Some(edge) = self.found_by(node) {
+            chain.push(edge);
+            node = edge.from;
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::{Edge, Graph};
+    use crate::hostmask::HostMask;
+    use crate::traversal::Sender;
+    use std::str::FromStr;
+
+    fn edge(from: usize, to: usize, dimension: &'static str, depth: usize) -> Edge {
+        Edge {
+            from,
+            to,
+            dimension,
+            pattern: String::new(),
+            depth,
+            weight: 1.0,
+            cross_network: false,
+            observed: None,
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut graph = Graph::new(Sender::seed(HostMask::from_str("a!b@c").unwrap()));
+        let (a, new) = graph.insert(Sender::stored(1, "a!b@c"));
+        assert!(new);
+        let (b, _) = graph.insert(Sender::stored(2, "b!b@c"));
+        let (c, _) = graph.insert(Sender::stored(3, "b!x@y"));
+        assert_eq!(graph.insert(Sender::stored(2, "b!b@c")), (b, false));
+
+        graph.link(edge(Graph::SEED, a, "nick", 1));
+        graph.link(edge(Graph::SEED, b, "host", 1));
+        graph.link(edge(a, b, "host", 2));
+        graph.link(edge(b, c, "nick", 2));
+        // duplicates and self links are dropped
+        graph.link(edge(b, c, "nick", 2));
+        graph.link(edge(c, c, "host", 3));
+
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.edges().len(), 4);
+        assert_eq!(graph.found_by(Graph::SEED), None);
+        assert_eq!(graph.found_by(b).unwrap().dimension, "host");
+        assert_eq!(graph.found_by(b).unwrap().from, Graph::SEED);
+        assert_eq!(
+            graph
+                .chain(c)
+                .iter()
+                .map(|e| (e.from, e.to))
+                .collect::<Vec<_>>(),
+            [(Graph::SEED, b), (b, c)]
+        );
+        assert!(graph.chain(Graph::SEED).is_empty());
+    }
+}
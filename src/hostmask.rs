@@ -1,6 +1,11 @@
+use crate::stoplist::Stoplist;
+use clap::ValueEnum;
 use itertools::Itertools;
 use regex::Regex;
-use std::net::IpAddr;
+use serde::Deserialize;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 use thiserror::Error;
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -8,44 +13,168 @@ pub struct HostMask {
     nick: Nick,
     ident: Ident,
     host: Host,
-    pub subnet: bool,
 }
 
 impl FromStr for HostMask {
     type Err = HostMaskError;
 
+    /// Parses a full `nick!ident@host`, where any part may use the `*` and `?`
+    /// wildcards, e.g. `*!*@1.2.3.*`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut chars = s.chars();
-        // get our relative positions
-        let nick = chars
-            .position(|ch| ch.eq(&'!'))
-            .ok_or(HostMaskError::MissingIdent)?;
-        let ident = chars
-            .position(|ch| ch.eq(&'@'))
-            .ok_or(HostMaskError::MissingHost)?;
-
-        // collect and return
-        // skipping joining characters
-        Ok(Self {
-            nick: (&s[0..nick]).into(),
-            ident: (&s[nick + 1..=nick + ident]).into(),
-            host: (&s[nick + ident + 2..]).into(),
-            subnet: false,
-        })
+        match s.parse()? {
+            Prefix::User(mask) => Ok(mask),
+            Prefix::Server(name) => Err(HostMaskError::Server(name)),
+        }
     }
 }
 
-impl HostMask {
-    pub fn nick_query(&self) -> String {
-        self.nick.query()
+/// Where a message came from, per RFC 2812 section 2.3.1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Prefix {
+    /// e.g. `irc.libera.chat`, for numerics and server notices
+    Server(String),
+    User(HostMask),
+}
+
+impl FromStr for Prefix {
+    type Err = HostMaskError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((nick, rest)) = s.split_once('!') else {
+            if !s.contains('@') && s.contains('.') {
+                return server(s).map(Prefix::Server);
+            }
+            return Err(HostMaskError::MissingIdent);
+        };
+        let (ident, host) = rest.split_once('@').ok_or(HostMaskError::MissingHost)?;
+        Ok(Prefix::User(HostMask {
+            nick: validate_nick(nick)?.into(),
+            ident: validate_ident(ident)?.into(),
+            host: validate_host(host)?.into(),
+        }))
+    }
+}
+
+/// RFC 2812 `special`, which nicks may use alongside letters and digits.
+const SPECIAL: &str = "[]\\`_^{|}";
+const WILDCARDS: &str = "*?";
+
+/// Letters, digits and specials, with `-` and digits barred from the front.
+/// Non-ASCII letters are let through for networks that allow UTF-8 nicks.
+fn validate_nick(nick: &str) -> Result<&str, HostMaskError> {
+    let first = nick.chars().next().ok_or(HostMaskError::EmptyNick)?;
+    if first.is_ascii_digit() || first == '-' {
+        return Err(HostMaskError::NickStart(first));
+    }
+    match nick.chars().find(|&ch| {
+        !(ch.is_alphanumeric() || ch == '-' || SPECIAL.contains(ch) || WILDCARDS.contains(ch))
+    }) {
+        Some(ch) => Err(HostMaskError::InvalidNick(ch)),
+        None => Ok(nick),
+    }
+}
+
+/// RFC 2812 `user`: anything but NUL, CR, LF, space and `@`.
+fn validate_ident(ident: &str) -> Result<&str, HostMaskError> {
+    if ident.is_empty() {
+        return Err(HostMaskError::EmptyIdent);
+    }
+    match ident
+        .chars()
+        .find(|ch| matches!(ch, '\0' | '\r' | '\n' | ' ' | '@'))
+    {
+        Some(ch) => Err(HostMaskError::InvalidIdent(ch)),
+        None => Ok(ident),
+    }
+}
+
+/// Hosts are rarely RFC hostnames: cloaks have `/`, IPv6 addresses have `:`
+/// and so on, so only whitespace, control characters and `!`/`@` are refused.
+fn validate_host(host: &str) -> Result<&str, HostMaskError> {
+    if host.is_empty() {
+        return Err(HostMaskError::EmptyHost);
+    }
+    match host
+        .chars()
+        .find(|&ch| ch.is_whitespace() || ch.is_control() || ch == '!' || ch == '@')
+    {
+        Some(ch) => Err(HostMaskError::InvalidHost(ch)),
+        None => Ok(host),
     }
+}
+
+/// A dotted server name, which may itself be a mask like `*.libera.chat`.
+fn server(name: &str) -> Result<String, HostMaskError> {
+    match name.chars().find(|&ch| {
+        !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' || WILDCARDS.contains(ch))
+    }) {
+        Some(ch) => Err(HostMaskError::InvalidServer(ch)),
+        None => Ok(name.into()),
+    }
+}
 
-    pub fn ident_query(&self) -> String {
-        self.ident.query()
+/// Escapes `term` for a LIKE pattern, turning the `*` and `?` wildcards into
+/// `%` and `_`.
+fn like(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len());
+    for ch in term.chars() {
+        match ch {
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            '%' | '_' | '\\' => {
+                pattern.push('\\');
+                pattern.push(ch);
+            }
+            ch => pattern.push(ch),
+        }
     }
+    pattern
+}
 
-    pub fn host_query(&self) -> String {
-        self.host.query()
+/// Escapes `term` for a LIKE pattern that matches it literally.
+fn escape(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len());
+    for ch in term.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(ch);
+    }
+    pattern
+}
+
+/// Whether `term` is nothing but `*`.
+fn wildcard(term: &str) -> bool {
+    term.chars().all(|ch| ch == '*')
+}
+
+impl Display for HostMask {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}!{}@{}", self.nick, self.ident, self.host)
+    }
+}
+
+impl HostMask {
+    /// Splits a mask at its first `!` and last `@` without holding the parts
+    /// to what IRC allows, for seeds with regexes in them and for senders the
+    /// core has already seen, which servers let through whatever we think.
+    pub fn unchecked(s: &str) -> Result<Self, HostMaskError> {
+        let (nick, rest) = s.split_once('!').ok_or(HostMaskError::MissingIdent)?;
+        let (ident, host) = rest.rsplit_once('@').ok_or(HostMaskError::MissingHost)?;
+        if nick.is_empty() {
+            return Err(HostMaskError::EmptyNick);
+        }
+        if ident.is_empty() {
+            return Err(HostMaskError::EmptyIdent);
+        }
+        if host.is_empty() {
+            return Err(HostMaskError::EmptyHost);
+        }
+        Ok(Self {
+            nick: nick.into(),
+            ident: ident.into(),
+            host: host.into(),
+        })
     }
 
     pub fn nick(&self) -> &Nick {
@@ -59,6 +188,14 @@ impl HostMask {
     pub fn host(&self) -> &Host {
         &self.host
     }
+
+    /// The client's IPv4 address, when a web client put it in the ident.
+    pub fn web_address(&self) -> Option<IpAddr> {
+        self.host
+            .is_webchat()
+            .then(|| self.ident.address())
+            .flatten()
+    }
 }
 
 #[derive(Debug, Error)]
@@ -67,18 +204,183 @@ pub enum HostMaskError {
     MissingIdent,
     #[error("missing '@' symbol; cannot find host")]
     MissingHost,
+    #[error("empty nick")]
+    EmptyNick,
+    #[error("empty ident")]
+    EmptyIdent,
+    #[error("empty host")]
+    EmptyHost,
+    #[error("nicks cannot start with {0:?}")]
+    NickStart(char),
+    #[error("{0:?} is not allowed in a nick")]
+    InvalidNick(char),
+    #[error("{0:?} is not allowed in an ident")]
+    InvalidIdent(char),
+    #[error("{0:?} is not allowed in a host")]
+    InvalidHost(char),
+    #[error("{0:?} is not allowed in a server name")]
+    InvalidServer(char),
+    #[error("{0:?} is a server, not a user")]
+    Server(String),
+}
+
+/// How loosely query terms match, set per pivot rather than per term.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct MatchOptions {
+    /// compare addresses by subnet rather than exactly
+    pub subnet: Option<Subnet>,
+    /// how terms are compared against their part of each mask; `None` for
+    /// the dimension's usual shape
+    pub mode: Option<MatchMode>,
+    /// terms are senders' own data rather than the seed's patterns, so
+    /// [`MatchMode::Regex`] escapes them
+    pub literal: bool,
+}
+
+/// How a term is compared against its part of each sender's mask.
+#[derive(ValueEnum, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    /// the whole part
+    Exact,
+    /// the start of the part
+    Prefix,
+    /// the end of the part
+    Suffix,
+    /// anywhere in the part
+    Substring,
+    /// the whole part, ignoring case
+    #[value(alias = "case-insensitive")]
+    #[serde(alias = "case-insensitive")]
+    Insensitive,
+    /// the seed's part is a case-insensitive POSIX regex for the whole part
+    Regex,
+}
+
+/// A pattern over `nick!ident@host` for one of postgres' matching operators.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Pattern {
+    /// `LIKE`, escaped with `\`
+    Like(String),
+    /// `ILIKE`, escaped with `\`
+    ILike(String),
+    /// `~*`
+    Regex(String),
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Like(pattern) => f.write_str(pattern),
+            Pattern::ILike(pattern) => write!(f, "ILIKE {pattern}"),
+            Pattern::Regex(pattern) => write!(f, "~* {pattern}"),
+        }
+    }
+}
+
+/// Which column of the `sender` table a query's patterns are matched against.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Column {
+    /// `nick!ident@host`
+    Sender,
+    Realname,
+    /// `avatarurl`, which only newer cores keep
+    Avatar,
+}
+
+/// Which part of `nick!ident@host` a term is compared against.
+#[derive(Debug, Clone, Copy)]
+enum Part {
+    Nick,
+    Ident,
+    Host,
+}
+
+impl Part {
+    /// Shapes `term` into a pattern matching this part of a mask by `mode`.
+    fn pattern(self, term: &str, mode: MatchMode) -> Pattern {
+        // nicks can't hold `!` and idents can't hold `@`, so the first `!`
+        // and the last `@` are always the separators
+        let (before, after) = match self {
+            Part::Nick => ("", "!%"),
+            Part::Ident => ("%!", "@%"),
+            Part::Host => ("%@", ""),
+        };
+        let term = like(term);
+        match mode {
+            MatchMode::Exact => Pattern::Like(format!("{before}{term}{after}")),
+            MatchMode::Prefix => Pattern::Like(format!("{before}{term}%{after}")),
+            MatchMode::Suffix => Pattern::Like(format!("{before}%{term}{after}")),
+            MatchMode::Substring => Pattern::Like(format!("{before}%{term}%{after}")),
+            MatchMode::Insensitive => Pattern::ILike(format!("{before}{term}{after}")),
+            MatchMode::Regex => unreachable!("regexes are shaped by Part::regex"),
+        }
+    }
+
+    /// Wraps `regex` so it must match this part whole.
+    fn regex(self, regex: &str, options: &MatchOptions) -> Pattern {
+        let regex = if options.literal {
+            regex::escape(regex)
+        } else {
+            regex.to_string()
+        };
+        Pattern::Regex(match self {
+            Part::Nick => format!("^(?:{regex})!"),
+            Part::Ident => format!("^[^!]*!~?(?:{regex})@"),
+            Part::Host => format!("@(?:{regex})$"),
+        })
+    }
 }
 
-pub trait Query {
-    fn query(&self) -> String;
+pub trait Query: Display {
+    /// A pattern over `nick!ident@host`, or whatever [`Query::column`] says;
+    /// may match more than it should.
+    fn query(&self, options: &MatchOptions) -> Pattern;
+
+    /// Every pattern to look up, for terms that can be spelled more than one way.
+    fn patterns(&self, options: &MatchOptions) -> Vec<Pattern> {
+        vec![self.query(options)]
+    }
+
+    /// What the patterns are matched against.
+    fn column(&self) -> Column {
+        Column::Sender
+    }
+
+    /// Whether `candidate`, one of the senders `query` turned up, really matches.
+    fn matches(&self, _candidate: &HostMask, _options: &MatchOptions) -> bool {
+        true
+    }
+
+    /// How strongly sharing this term with `candidate` suggests the same
+    /// person is behind both, from 0 to 1.
+    fn weight(&self, _candidate: &HostMask) -> f32 {
+        1.0
+    }
+
+    /// Whether the term is a bare `*`, which matches every sender and so
+    /// links nothing.
+    fn is_wildcard(&self) -> bool;
+
+    /// Whether `stoplist` rules the term out as too widely shared to pivot on.
+    fn stopped(&self, _stoplist: &Stoplist) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Nick(String);
 
 impl Query for Nick {
-    fn query(&self) -> String {
-        format!("{}%", self.0)
+    fn query(&self, options: &MatchOptions) -> Pattern {
+        match options.mode.unwrap_or(MatchMode::Prefix) {
+            MatchMode::Regex => Part::Nick.regex(&self.0, options),
+            mode => Part::Nick.pattern(&self.0, mode),
+        }
+    }
+
+    fn is_wildcard(&self) -> bool {
+        wildcard(&self.0)
     }
 }
 
@@ -88,90 +390,615 @@ impl From<&str> for Nick {
     }
 }
 
-impl ToString for Nick {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl Display for Nick {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Nick {
+    /// The nick as an rfc1459 server compares it: ASCII lowercase, with `[]\\`
+    /// folded into `{}|`.
+    pub fn casefold(&self) -> String {
+        self.0
+            .chars()
+            .map(|ch| match ch {
+                '[' => '{',
+                ']' => '}',
+                '\\' => '|',
+                ch => ch.to_ascii_lowercase(),
+            })
+            .collect()
+    }
+
+    /// The family this nick belongs to: its casefolded form with trailing
+    /// `_`, `^`, `[m]` and `|suffix` decorations stripped, and trailing digits
+    /// too if `digits`, so `Foo[m]`, `foo_` and `foo|away` are all `foo`.
+    pub fn family(&self, digits: bool) -> NickFamily {
+        let mut stem = self.casefold();
+        loop {
+            let stripped = match stem.find('|') {
+                Some(bar) if bar > 0 => &stem[..bar],
+                _ => stem
+                    .strip_suffix("{m}")
+                    .unwrap_or(&stem)
+                    .trim_end_matches(['_', '^']),
+            };
+            let stripped = if digits {
+                stripped.trim_end_matches(|ch: char| ch.is_ascii_digit())
+            } else {
+                stripped
+            };
+            // a nick that's all decoration is its own family
+            if stripped.is_empty() || stripped == stem {
+                break;
+            }
+            stem = stripped.to_string();
+        }
+        NickFamily { stem, digits }
+    }
+}
+
+/// Nicks that differ only by case and decoration; see [`Nick::family`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct NickFamily {
+    stem: String,
+    digits: bool,
+}
+
+impl Query for NickFamily {
+    /// Every nick starting with the stem, in any case; [`NickFamily::matches`]
+    /// weeds out those from other families. Match modes don't apply.
+    fn query(&self, _options: &MatchOptions) -> Pattern {
+        // ILIKE folds case but can't fold `[]\\` into `{}|`, so those match any character
+        let stem = self
+            .stem
+            .chars()
+            .map(|ch| if "{}|".contains(ch) { '?' } else { ch })
+            .collect::<String>();
+        Pattern::ILike(format!("{}%!%", like(&stem)))
+    }
+
+    fn matches(&self, candidate: &HostMask, _options: &MatchOptions) -> bool {
+        // a seed with wildcards in it is matched by pattern alone
+        self.stem.contains(['*', '?']) || candidate.nick.family(self.digits) == *self
+    }
+
+    fn is_wildcard(&self) -> bool {
+        wildcard(&self.stem)
+    }
+}
+
+impl Display for NickFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stem)
     }
 }
+/// An ident, and whether identd vouched for it rather than the client
+/// choosing it, which servers mark with a leading `~`.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
-pub struct Ident(String);
+pub struct Ident {
+    name: String,
+    verified: bool,
+}
 
 impl Query for Ident {
-    fn query(&self) -> String {
-        format!("%{}%", self.0)
+    fn query(&self, options: &MatchOptions) -> Pattern {
+        self.patterns(options).remove(0)
+    }
+
+    /// Matches the ident whether or not identd vouched for it, so `bob`
+    /// finds `~bob` and back.
+    fn patterns(&self, options: &MatchOptions) -> Vec<Pattern> {
+        match options.mode.unwrap_or(MatchMode::Exact) {
+            MatchMode::Regex => vec![Part::Ident.regex(&self.name, options)],
+            // a `~` is just another character before a suffix or substring
+            mode @ (MatchMode::Suffix | MatchMode::Substring) => {
+                vec![Part::Ident.pattern(&self.name, mode)]
+            }
+            mode => vec![
+                Part::Ident.pattern(&self.name, mode),
+                Part::Ident.pattern(&format!("~{}", self.name), mode),
+            ],
+        }
+    }
+
+    /// Only identd vouching for both ends makes a shared ident strong evidence.
+    fn weight(&self, candidate: &HostMask) -> f32 {
+        if self.verified && candidate.ident.verified {
+            1.0
+        } else {
+            0.5
+        }
+    }
+
+    fn is_wildcard(&self) -> bool {
+        wildcard(&self.name)
+    }
+
+    fn stopped(&self, stoplist: &Stoplist) -> bool {
+        (self.is_generic() && !stoplist.generic_idents) || stoplist.stops_ident(&self.name)
+    }
+}
+
+/// Idents that clients, bouncers and web gateways give everyone alike.
+const GENERIC_IDENTS: &[&str] = &["user", "quassel", "znc", "irc", "webchat", "kiwiirc"];
+
+impl Ident {
+    /// Whether identd vouched for the ident.
+    pub fn verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Whether many unrelated people share this ident, like `~user`, or
+    /// irccloud's `~sid12345`, which says no more than its host does.
+    pub fn is_generic(&self) -> bool {
+        let name = self.name.to_ascii_lowercase();
+        GENERIC_IDENTS.contains(&name.as_str())
+            || ["sid", "uid"].iter().any(|prefix| {
+                name.strip_prefix(prefix)
+                    .is_some_and(|id| !id.is_empty() && id.chars().all(|ch| ch.is_ascii_digit()))
+            })
+    }
+
+    /// The ident read as the 8 hex digit IPv4 address web clients put there,
+    /// e.g. `~42cdc033` for 66.205.192.51. Plenty of other idents look like
+    /// one, so only [`HostMask::web_address`] trusts it.
+    fn address(&self) -> Option<IpAddr> {
+        let hex = &self.name;
+        if hex.len() != 8 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .map(|address| IpAddr::V4(Ipv4Addr::from(address)))
     }
 }
 
 impl From<&str> for Ident {
+    fn from(s: &str) -> Self {
+        match s.strip_prefix('~') {
+            Some(name) => Self {
+                name: name.into(),
+                verified: false,
+            },
+            None => Self {
+                name: s.into(),
+                verified: true,
+            },
+        }
+    }
+}
+
+impl Display for Ident {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.verified {
+            f.write_str("~")?;
+        }
+        f.write_str(&self.name)
+    }
+}
+
+/// A sender's realname, or gecos, which clients let people type anything
+/// into, so it never holds wildcards.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct Realname(String);
+
+impl Query for Realname {
+    fn query(&self, options: &MatchOptions) -> Pattern {
+        let term = escape(&self.0);
+        match options.mode.unwrap_or(MatchMode::Exact) {
+            MatchMode::Exact => Pattern::Like(term),
+            MatchMode::Prefix => Pattern::Like(format!("{term}%")),
+            MatchMode::Suffix => Pattern::Like(format!("%{term}")),
+            MatchMode::Substring => Pattern::Like(format!("%{term}%")),
+            MatchMode::Insensitive => Pattern::ILike(term),
+            MatchMode::Regex => Pattern::Regex(format!("^{}$", regex::escape(&self.0))),
+        }
+    }
+
+    fn column(&self) -> Column {
+        Column::Realname
+    }
+
+    /// Realnames are matched literally, and blank ones are never pivoted on.
+    fn is_wildcard(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+
+    fn stopped(&self, stoplist: &Stoplist) -> bool {
+        stoplist.stops_realname(&self.0)
+    }
+}
+
+impl From<&str> for Realname {
+    fn from(s: &str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl Display for Realname {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The URL of a sender's avatar, as IRCv3 metadata or a bridge reports it.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct Avatar(String);
+
+impl Query for Avatar {
+    /// URLs are compared whole, whatever the match mode.
+    fn query(&self, _options: &MatchOptions) -> Pattern {
+        Pattern::Like(escape(&self.0))
+    }
+
+    fn column(&self) -> Column {
+        Column::Avatar
+    }
+
+    fn is_wildcard(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+}
+
+impl From<&str> for Avatar {
     fn from(s: &str) -> Self {
         Self(s.into())
     }
 }
 
-impl ToString for Ident {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl Display for Avatar {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A services account, read off a cloak that only it can wear.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub struct Account(String);
+
+impl Query for Account {
+    /// Every cloak the account can wear, ignoring case as services do, so
+    /// `user/kks` finds `unaffiliated/KKS` and `user/kks/x-1234`.
+    fn query(&self, _options: &MatchOptions) -> Pattern {
+        Pattern::Regex(format!(
+            "@(?:user|unaffiliated|gateway/tor-sasl)/{}(?:/.*)?$",
+            regex::escape(&self.0)
+        ))
+    }
+
+    fn is_wildcard(&self) -> bool {
+        wildcard(&self.0)
+    }
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// How many leading bits of an address two hosts must share to be linked.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Subnet {
+    pub v4: u8,
+    pub v6: u8,
+}
+
+impl Subnet {
+    /// The whole address.
+    pub const EXACT: Self = Self { v4: 32, v6: 128 };
+
+    /// Whether `address` is in the network `self` wide around `network`.
+    pub fn contains(&self, network: IpAddr, address: IpAddr) -> bool {
+        match (network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let shift = 32 - u32::from(self.v4.min(32));
+                u32::from(network).checked_shr(shift) == u32::from(address).checked_shr(shift)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let shift = 128 - u32::from(self.v6.min(128));
+                u128::from(network).checked_shr(shift) == u128::from(address).checked_shr(shift)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for Subnet {
+    /// A /24 or a /64, the usual single customer allocations.
+    fn default() -> Self {
+        Self { v4: 24, v6: 64 }
+    }
+}
+
+/// What a host says about whoever is behind it, going by Libera's conventions.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum HostKind {
+    /// `user/name`, which only that account can wear
+    Account,
+    /// `project/.../name`, e.g. `libera/staff/name`, handed out by a project
+    Project,
+    /// `gateway/web/...` ending in a per-user `x-uid`, or any `gateway/` host
+    /// ending in the client's `ip.1.2.3.4`
+    WebGateway,
+    /// `gateway/tor-sasl/name`
+    Tor,
+    /// any other `gateway/` host ending in a per-user `x-uid`, e.g. a Matrix
+    /// user's `gateway/shell/matrix.org/x-uid`
+    Bridge,
+    /// any other `gateway/` host: shells, VPNs and web clients that don't say
+    /// who's connecting, all shared by many people
+    SharedBouncer,
+    /// a bare address or a reverse DNS name
+    Rdns,
+}
+
+impl HostKind {
+    pub fn of(host: &str) -> Self {
+        let parts = host.split('/').collect::<Vec<_>>();
+        match parts.as_slice() {
+            [_] => HostKind::Rdns,
+            ["user" | "unaffiliated", ..] => HostKind::Account,
+            ["gateway", "tor-sasl", ..] => HostKind::Tor,
+            ["gateway", "web", .., last] if last.starts_with("x-") => HostKind::WebGateway,
+            ["gateway", .., last] if last.starts_with("ip.") => HostKind::WebGateway,
+            ["gateway", .., last] if last.starts_with("x-") => HostKind::Bridge,
+            ["gateway", ..] => HostKind::SharedBouncer,
+            _ => HostKind::Project,
+        }
+    }
+}
+
+/// Labels of reverse DNS names that web clients connect from, beyond
+/// Libera's `gateway/web/` cloaks.
+const WEBCHAT_LABELS: &[&str] = &["webchat", "kiwiirc", "mibbit", "qwebirc", "cgiirc"];
+
+/// A host, the address found in it if any, and what kind of host it is.
+#[derive(Debug, Eq, PartialOrd, Ord, Clone)]
+pub struct Host(String, Option<IpAddr>, HostKind);
+
+impl Host {
+    /// Whether linking on this host says anything about who's behind it.
+    pub fn expands(&self) -> bool {
+        self.2 != HostKind::SharedBouncer
+    }
+
+    /// The account an account or tor-sasl cloak names, e.g. `kks` for
+    /// `user/kks/x-1234`.
+    pub fn account(&self) -> Option<Account> {
+        let mut parts = self.0.split('/');
+        let account = match self.2 {
+            HostKind::Account => parts.nth(1),
+            HostKind::Tor => parts.nth(2),
+            _ => None,
+        };
+        account
+            .filter(|account| !account.is_empty())
+            .map(|account| Account(account.into()))
+    }
+
+    /// Whether a web client connected from here, and so might carry its
+    /// user's address in the ident.
+    pub fn is_webchat(&self) -> bool {
+        match self.2 {
+            HostKind::Rdns => self
+                .0
+                .split('.')
+                .any(|label| WEBCHAT_LABELS.contains(&label.to_ascii_lowercase().as_str())),
+            _ => self.0.starts_with("gateway/web/"),
+        }
+    }
+
+    /// The address to compare by, unless the seed's host is a regex that
+    /// merely looks like one.
+    fn network(&self, options: &MatchOptions) -> Option<IpAddr> {
+        self.1.filter(|_| self.literal(options))
+    }
+
+    /// Whether the seed's host is a name rather than a regex.
+    fn literal(&self, options: &MatchOptions) -> bool {
+        options.literal || options.mode != Some(MatchMode::Regex)
+    }
+
+    /// Whether this is a reverse DNS name matched by suffix, which only counts
+    /// at a label boundary: `example.org` takes in `a.example.org` but not
+    /// `fooexample.org` or `gateway/shell/example.org`.
+    fn by_domain(&self, options: &MatchOptions) -> bool {
+        self.2 == HostKind::Rdns
+            && self.network(options).is_none()
+            && options.mode.unwrap_or(MatchMode::Suffix) == MatchMode::Suffix
     }
 }
-#[derive(Debug, Eq, PartialOrd, Ord, Hash, Clone)]
-pub struct Host(String, Option<IpAddr>, bool);
 
 impl Query for Host {
-    fn query(&self) -> String {
-        match self.1 {
+    /// For addresses, a rough prefilter: the octets, or hextets, that the
+    /// subnet covers. [`Host::matches`] does the real comparison. Cloaks and
+    /// gateway uids name one person, so fuzzy modes match them whole, and
+    /// reverse DNS names match as a domain.
+    fn query(&self, options: &MatchOptions) -> Pattern {
+        let subnet = options.subnet.unwrap_or(Subnet::EXACT);
+        match self.network(options) {
             Some(IpAddr::V4(v4)) => {
-                // Only want the first 3 octlets
-                let safe_mask = &v4.octets()[0..if self.2 { 3 } else { 4 }]
+                let octets = usize::from(subnet.v4.min(32) / 8);
+                // `_` stands in for whichever of `.` or `-` the host uses
+                let mut pattern = v4.octets()[..octets]
                     .iter()
-                    // convert octlets into chars, failures are translated to wildcards
                     .map(|oct| oct.to_string())
                     .join("_");
-                format!("%{safe_mask}%")
+                if octets < 4 {
+                    pattern.push('_');
+                }
+                Pattern::Like(format!("%{pattern}%"))
             }
-            _ => format!("%{}", self.0),
+            Some(IpAddr::V6(v6)) => {
+                let hextets = usize::from(subnet.v6.min(128) / 16);
+                // a zero hextet may be folded into a `::`, so stop before one
+                let pattern = v6.segments()[..hextets]
+                    .iter()
+                    .take_while(|&&hextet| hextet != 0)
+                    .map(|hextet| format!("{hextet:x}:"))
+                    .collect::<String>();
+                // without any to go on, only the address itself will do;
+                // either way hex digits come in either case
+                if pattern.is_empty() {
+                    Part::Host.pattern(&v6.to_string(), MatchMode::Insensitive)
+                } else {
+                    Pattern::ILike(format!("%{pattern}%"))
+                }
+            }
+            None => match options.mode.unwrap_or(MatchMode::Suffix) {
+                MatchMode::Regex => Part::Host.regex(&self.0, options),
+                mode @ MatchMode::Insensitive => Part::Host.pattern(&self.0, mode),
+                _ if self.2 != HostKind::Rdns => Part::Host.pattern(&self.0, MatchMode::Exact),
+                MatchMode::Suffix => Part::Host.pattern(&format!(".{}", self.0), MatchMode::Suffix),
+                mode => Part::Host.pattern(&self.0, mode),
+            },
+        }
+    }
+
+    /// IPv4 addresses are also looked for in hex idents, in either case, and
+    /// a domain is also looked for on its own.
+    fn patterns(&self, options: &MatchOptions) -> Vec<Pattern> {
+        let mut patterns = vec![self.query(options)];
+        if self.by_domain(options) {
+            patterns.push(Part::Host.pattern(&self.0, MatchMode::Exact));
+        }
+        if let Some(IpAddr::V4(v4)) = self.network(options) {
+            let subnet = options.subnet.unwrap_or(Subnet::EXACT);
+            let octets = usize::from(subnet.v4.min(32) / 8);
+            let hex = v4.octets()[..octets]
+                .iter()
+                .map(|oct| format!("{oct:02x}"))
+                .collect::<String>();
+            let hex = format!("%{hex:_<8}@%");
+            let upper = hex.to_ascii_uppercase();
+            patterns.extend([hex, upper].into_iter().dedup().map(Pattern::Like));
         }
+        patterns
+    }
+
+    fn matches(&self, candidate: &HostMask, options: &MatchOptions) -> bool {
+        let subnet = options.subnet.unwrap_or(Subnet::EXACT);
+        match self.network(options) {
+            Some(network) => [candidate.host.1, candidate.web_address()]
+                .into_iter()
+                .flatten()
+                .any(|address| subnet.contains(network, address)),
+            // a name says nothing about gateway or cloaked senders that
+            // happen to end in it
+            None if self.2 == HostKind::Rdns && self.literal(options) => {
+                candidate.host.2 == HostKind::Rdns
+            }
+            None => true,
+        }
+    }
+
+    fn is_wildcard(&self) -> bool {
+        wildcard(&self.0)
+    }
+
+    fn stopped(&self, stoplist: &Stoplist) -> bool {
+        stoplist.stops_host(&self.0)
     }
 }
 
-impl PartialEq for Host{
+impl PartialEq for Host {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
     }
 }
 
+impl Hash for Host {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
 impl From<&str> for Host {
     fn from(s: &str) -> Self {
-        if let Some(address) = Regex::new(r"((25[0-5]|(2[0-4]|1\d|[1-9]|)\d)(\.|-)?\b){4}")
-            .unwrap()
-            .find(s)
-        {
-            let mut addr = address.as_str().to_string();
-            if addr.ends_with('.') {
-                addr = addr[..addr.len() - 1].to_string();
-            }
-            addr = addr.replace("-", ".");
-            let address = addr.parse().ok();
-            return Self(s.into(), address, false);
-        }
-        if let Some(address) = Regex::new(r"(([0-9a-fA-F]{1,4}:){7,7}[0-9a-fA-F]{1,4}|([0-9a-fA-F]{1,4}:){1,7}:|([0-9a-fA-F]{1,4}:){1,6}:[0-9a-fA-F]{1,4}|([0-9a-fA-F]{1,4}:){1,5}(:[0-9a-fA-F]{1,4}){1,2}|([0-9a-fA-F]{1,4}:){1,4}(:[0-9a-fA-F]{1,4}){1,3}|([0-9a-fA-F]{1,4}:){1,3}(:[0-9a-fA-F]{1,4}){1,4}|([0-9a-fA-F]{1,4}:){1,2}(:[0-9a-fA-F]{1,4}){1,5}|[0-9a-fA-F]{1,4}:((:[0-9a-fA-F]{1,4}){1,6})|:((:[0-9a-fA-F]{1,4}){1,7}|:)|fe80:(:[0-9a-fA-F]{0,4}){0,4}%[0-9a-zA-Z]{1,}|::(ffff(:0{1,4}){0,1}:){0,1}((25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])|([0-9a-fA-F]{1,4}:){1,4}:((25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9]))").unwrap().find(s) {
-            Self(s.into(), Some(IpAddr::V6(address.as_str().parse().unwrap())), false)
-        } else {
-            Self(s.into(), None, false)
+        let kind = HostKind::of(s);
+        let address = match kind {
+            HostKind::Rdns => address(s),
+            // `gateway/.../ip.1.2.3.4` or `.../ip.2001:db8::1`
+            HostKind::WebGateway => s
+                .rsplit('/')
+                .next()
+                .and_then(|last| last.strip_prefix("ip."))
+                .and_then(|ip| ip.parse().ok()),
+            // cloaks are names, even when they look like `user/x-10-0-0-1`
+            _ => None,
+        };
+        Self(s.into(), address, kind)
+    }
+}
+
+impl From<IpAddr> for Host {
+    fn from(address: IpAddr) -> Self {
+        Self(address.to_string(), Some(address), HostKind::Rdns)
+    }
+}
+
+/// Finds an address in a host, dotted, dashed or embedded in a longer name.
+fn address(s: &str) -> Option<IpAddr> {
+    if let Some(address) = Regex::new(r"((25[0-5]|(2[0-4]|1\d|[1-9]|)\d)(\.|-)?\b){4}")
+        .unwrap()
+        .find(s)
+    {
+        let mut addr = address.as_str().to_string();
+        if addr.ends_with('.') {
+            addr = addr[..addr.len() - 1].to_string();
         }
+        addr = addr.replace("-", ".");
+        return addr.parse().ok();
     }
+    // the longest run that could be an address, so `2001:db8::5` isn't cut short
+    s.split(|ch: char| !(ch.is_ascii_hexdigit() || ch == ':' || ch == '.'))
+        .filter(|token| token.contains(':'))
+        .find_map(|token| token.parse::<Ipv6Addr>().ok())
+        .map(IpAddr::V6)
 }
 
-impl ToString for Host {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl Display for Host {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 #[cfg(test)]
 mod test {
-    use crate::hostmask::{Host, HostMask, Query};
+    use crate::hostmask::{
+        Avatar, Column, Host, HostKind, HostMask, HostMaskError, Ident, MatchMode, MatchOptions,
+        Nick, Pattern, Prefix, Query, Realname, Subnet,
+    };
+    use proptest::prelude::*;
     use std::net::{IpAddr, Ipv4Addr};
     use std::str::FromStr;
 
+    const FUZZY: MatchOptions = MatchOptions {
+        subnet: None,
+        mode: None,
+        literal: false,
+    };
+
+    fn subnet(v4: u8, v6: u8) -> MatchOptions {
+        MatchOptions {
+            subnet: Some(Subnet { v4, v6 }),
+            ..FUZZY
+        }
+    }
+
+    fn mode(mode: MatchMode) -> MatchOptions {
+        MatchOptions {
+            mode: Some(mode),
+            ..FUZZY
+        }
+    }
+
+    fn shown(patterns: Vec<Pattern>) -> Vec<String> {
+        patterns.iter().map(Pattern::to_string).collect()
+    }
+
     #[test]
     fn test_parse_hostmask() {
         assert_eq!(
@@ -180,7 +1007,6 @@ mod test {
                 nick: "Disconsented".into(),
                 ident: "~quassel".into(),
                 host: "irc.disconsented.com".into(),
-                subnet: false,
             }
         );
         assert_eq!(
@@ -189,9 +1015,118 @@ mod test {
                 nick: "Unit640".into(),
                 ident: "~Unit640".into(),
                 host: "user/Unit640".into(),
-                subnet: false,
             }
-        )
+        );
+        // offsets are bytes, not chars
+        let mask = HostMask::from_str("Zoë!~zoë@hôte.example").unwrap();
+        assert_eq!(mask.nick(), &Nick::from("Zoë"));
+        assert_eq!(mask.ident(), &Ident::from("~zoë"));
+        assert_eq!(mask.host(), &Host::from("hôte.example"));
+        // only the first '!' and '@' split
+        assert_eq!(
+            HostMask::from_str("a!b!c@d").unwrap().ident(),
+            &Ident::from("b!c")
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| HostMask::from_str(s).unwrap_err();
+        assert!(matches!(error("kks"), HostMaskError::MissingIdent));
+        assert!(matches!(error("kks@host"), HostMaskError::MissingIdent));
+        assert!(matches!(error("kks!kks"), HostMaskError::MissingHost));
+        assert!(matches!(error("!kks@host"), HostMaskError::EmptyNick));
+        assert!(matches!(error("kks!@host"), HostMaskError::EmptyIdent));
+        assert!(matches!(error("kks!kks@"), HostMaskError::EmptyHost));
+        assert!(matches!(error("9kks!k@h"), HostMaskError::NickStart('9')));
+        assert!(matches!(error("-kks!k@h"), HostMaskError::NickStart('-')));
+        assert!(matches!(error("k,s!k@h"), HostMaskError::InvalidNick(',')));
+        assert!(matches!(
+            error("kks!k s@h"),
+            HostMaskError::InvalidIdent(' ')
+        ));
+        assert!(matches!(
+            error("kks!k@h@h"),
+            HostMaskError::InvalidHost('@')
+        ));
+        assert!(matches!(
+            error("kks!k@h\r"),
+            HostMaskError::InvalidHost('\r')
+        ));
+        assert!(matches!(error("irc.libera.chat"), HostMaskError::Server(_)));
+        assert!(matches!(
+            Prefix::from_str("irc_libera.chat"),
+            Err(HostMaskError::InvalidServer('_'))
+        ));
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(
+            Prefix::from_str("*.libera.chat").unwrap(),
+            Prefix::Server("*.libera.chat".into())
+        );
+        assert_eq!(
+            Prefix::from_str("kks!~kks@user/kks").unwrap(),
+            Prefix::User(HostMask::from_str("kks!~kks@user/kks").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let mask = HostMask::from_str("*!*@1.2.3.*").unwrap();
+        assert_eq!(mask.nick().query(&FUZZY).to_string(), "%%!%");
+        assert!(mask.nick().is_wildcard() && mask.ident().is_wildcard());
+        assert!(!mask.host().is_wildcard());
+        assert_eq!(mask.host().query(&FUZZY).to_string(), "%@%.1.2.3.%");
+        assert_eq!(Nick::from("k?s").query(&FUZZY).to_string(), "k_s%!%");
+        // literal LIKE metacharacters are escaped
+        assert_eq!(Nick::from("kks_").query(&FUZZY).to_string(), "kks\\_%!%");
+        assert_eq!(Ident::from("100%").query(&FUZZY).to_string(), "%!100\\%@%");
+        assert_eq!(Host::from("a\\b").query(&FUZZY).to_string(), "%@%.a\\\\b");
+    }
+
+    /// Nicks as RFC 2812 has them, plus a few non-ASCII letters.
+    fn nick() -> impl Strategy<Value = String> {
+        "[a-zA-Z\\[\\]\\\\`_^{|}éß藤][a-zA-Z0-9\\[\\]\\\\`_^{|}éß藤-]{0,15}"
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_never_panics(s in "\\PC*") {
+            let _ = HostMask::from_str(&s);
+        }
+
+        #[test]
+        fn test_parse_separators_never_panic(s in "[!@a-zé藤 .*?~-]{0,12}") {
+            let _ = Prefix::from_str(&s);
+        }
+
+        #[test]
+        fn test_parse_roundtrip(
+            nick in nick(),
+            ident in "~?[a-zA-Z0-9_.é-]{1,10}",
+            host in "[a-zA-Z0-9/:.é-]{1,30}",
+        ) {
+            let s = format!("{nick}!{ident}@{host}");
+            let mask = HostMask::from_str(&s).unwrap();
+            prop_assert_eq!(mask.to_string(), s);
+            prop_assert_eq!(mask.nick(), &Nick::from(nick.as_str()));
+            prop_assert_eq!(mask.ident(), &Ident::from(ident.as_str()));
+            prop_assert_eq!(mask.host(), &Host::from(host.as_str()));
+        }
+
+        #[test]
+        fn test_parse_rejects_bad_nicks(
+            nick in nick(),
+            bad in "[ ,.:#&\"'/]",
+            at in 0usize..16,
+        ) {
+            let at = nick.char_indices().map(|(i, _)| i).nth(at).unwrap_or(nick.len());
+            let nick = format!("{}{bad}{}", &nick[..at], &nick[at..]);
+            let mask = format!("{nick}!~a@b");
+            prop_assert!(HostMask::from_str(&mask).is_err());
+        }
     }
 
     #[test]
@@ -207,10 +1142,11 @@ mod test {
                 Host(
                     raw_ip.into(),
                     Some(IpAddr::V4(raw_ip.parse().unwrap())),
-                    false
+                    HostKind::Rdns
                 )
             );
-            assert_eq!(raw_host.query(), "%66_205_192%");
+            assert_eq!(raw_host.query(&FUZZY).to_string(), "%66_205_192_51%");
+            assert_eq!(raw_host.query(&subnet(24, 64)).to_string(), "%66_205_192_%");
         }
         {
             let raw_host = Host::from(dotted_mask);
@@ -219,10 +1155,14 @@ mod test {
                 Host(
                     dotted_mask.into(),
                     Some(IpAddr::V4(Ipv4Addr::new(188, 147, 100, 240))),
-                    false
+                    HostKind::Rdns
                 )
             );
-            assert_eq!(raw_host.query(), "%188_147_100%");
+            assert_eq!(raw_host.query(&FUZZY).to_string(), "%188_147_100_240%");
+            assert_eq!(
+                raw_host.query(&subnet(24, 64)).to_string(),
+                "%188_147_100_%"
+            );
         }
         {
             let raw_host = Host::from(dashed_mask);
@@ -231,15 +1171,358 @@ mod test {
                 Host(
                     dashed_mask.into(),
                     Some(IpAddr::V4(Ipv4Addr::new(87, 248, 67, 133))),
-                    false
+                    HostKind::Rdns
                 )
             );
-            assert_eq!(raw_host.query(), "%87_248_67%");
+            assert_eq!(raw_host.query(&FUZZY).to_string(), "%87_248_67_133%");
+            assert_eq!(raw_host.query(&subnet(24, 64)).to_string(), "%87_248_67_%");
         }
         {
             let raw_host = Host::from(no_ip);
-            assert_eq!(Host::from(no_ip), Host(no_ip.into(), None, false));
-            assert_eq!(raw_host.query(), "%user/kks");
+            assert_eq!(
+                Host::from(no_ip),
+                Host(no_ip.into(), None, HostKind::Account)
+            );
+            assert_eq!(raw_host.query(&FUZZY).to_string(), "%@user/kks");
+        }
+    }
+
+    fn mask(host: &str) -> HostMask {
+        HostMask::from_str(&format!("a!b@{host}")).unwrap()
+    }
+
+    #[test]
+    fn test_subnet() {
+        let host = Host::from("66.205.192.51");
+        let slash24 = subnet(24, 64);
+        // dotted, dashed and embedded forms of the same /24
+        assert!(host.matches(&mask("66.205.192.77"), &slash24));
+        assert!(host.matches(&mask("66-205-192-1.static.example.net"), &slash24));
+        assert!(host.matches(&mask("static-ip-66-205-192-200.example.pl"), &slash24));
+        assert!(host.matches(&mask("66.205.192.9.nat.example.pl"), &slash24));
+        // which the LIKE prefilter lets through but aren't in it
+        assert!(!host.matches(&mask("166.205.192.5"), &slash24));
+        assert!(!host.matches(&mask("66.205.193.51"), &slash24));
+        assert!(!host.matches(&mask("user/kks"), &slash24));
+        assert!(!host.matches(&mask("2001:db8::1"), &slash24));
+
+        let slash16 = subnet(16, 64);
+        assert_eq!(host.query(&slash16).to_string(), "%66_205_%");
+        assert!(host.matches(&mask("66-205-7-7.example.net"), &slash16));
+        assert!(!host.matches(&mask("66.204.192.51"), &slash16));
+
+        // without a subnet the whole address has to match
+        assert!(host.matches(&mask("x-66-205-192-51.example.net"), &FUZZY));
+        assert!(!host.matches(&mask("66.205.192.52"), &FUZZY));
+
+        let v6 = Host::from("2001:db8:1:2::5");
+        assert_eq!(v6.1, "2001:db8:1:2::5".parse().ok());
+        assert_eq!(v6.query(&FUZZY).to_string(), "ILIKE %2001:db8:1:2:%");
+        assert!(!v6.matches(&mask("2001:db8:1:2::6"), &FUZZY));
+        let slash64 = subnet(24, 64);
+        assert_eq!(v6.query(&slash64).to_string(), "ILIKE %2001:db8:1:2:%");
+        assert_eq!(v6.query(&subnet(24, 48)).to_string(), "ILIKE %2001:db8:1:%");
+        assert_eq!(v6.query(&subnet(24, 40)).to_string(), "ILIKE %2001:db8:%");
+        assert_eq!(
+            Host::from("2001:db8:0:1::5").query(&slash64).to_string(),
+            "ILIKE %2001:db8:%"
+        );
+        // with no hextet to go on, only the address itself will do
+        let loopback = Host::from("::1");
+        assert_eq!(loopback.query(&slash64).to_string(), "ILIKE %@::1");
+        assert!(v6.matches(&mask("2001:DB8:1:2::6"), &slash64));
+        assert!(v6.matches(&mask("2001:db8:1:2:ffff::1"), &slash64));
+        assert!(!v6.matches(&mask("2001:db8:1:3::5"), &slash64));
+        let slash48 = subnet(24, 48);
+        assert!(v6.matches(&mask("2001:db8:1:3::5"), &slash48));
+        assert!(!v6.matches(&mask("2001:db8:2::5"), &slash48));
+
+        // non-address hosts are matched by their pattern alone
+        assert!(Host::from("user/kks").matches(&mask("user/kks"), &slash24));
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let nick = Nick::from("foo_bar");
+        for (m, pattern) in [
+            (MatchMode::Exact, "foo\\_bar!%"),
+            (MatchMode::Prefix, "foo\\_bar%!%"),
+            (MatchMode::Suffix, "%foo\\_bar!%"),
+            (MatchMode::Substring, "%foo\\_bar%!%"),
+            (MatchMode::Insensitive, "ILIKE foo\\_bar!%"),
+            (MatchMode::Regex, "~* ^(?:foo_bar)!"),
+        ] {
+            assert_eq!(nick.query(&mode(m)).to_string(), pattern, "{m:?}");
+        }
+        assert_eq!(
+            shown(Ident::from("~kks").patterns(&mode(MatchMode::Prefix))),
+            ["%!kks%@%", "%!~kks%@%"]
+        );
+        assert_eq!(
+            shown(Ident::from("kks").patterns(&mode(MatchMode::Substring))),
+            ["%!%kks%@%"]
+        );
+        assert_eq!(
+            Host::from("example.net").query(&mode(MatchMode::Exact)),
+            Pattern::Like("%@example.net".into())
+        );
+        assert_eq!(
+            Host::from("example.net").query(&mode(MatchMode::Substring)),
+            Pattern::Like("%@%example.net%".into())
+        );
+        // cloaks only ever match whole
+        for m in [MatchMode::Prefix, MatchMode::Suffix, MatchMode::Substring] {
+            assert_eq!(
+                Host::from("user/kks").query(&mode(m)),
+                Pattern::Like("%@user/kks".into())
+            );
+        }
+        assert_eq!(
+            Host::from("user/KKS").query(&mode(MatchMode::Insensitive)),
+            Pattern::ILike("%@user/KKS".into())
+        );
+
+        // every LIKE metacharacter in a term is escaped, whatever the mode
+        for m in [MatchMode::Exact, MatchMode::Insensitive] {
+            assert_eq!(
+                Nick::from("50%").query(&mode(m)).to_string(),
+                format!(
+                    "{}50\\%!%",
+                    if m == MatchMode::Exact { "" } else { "ILIKE " }
+                )
+            );
+        }
+        assert_eq!(
+            Nick::from("back\\slash").query(&mode(MatchMode::Exact)),
+            Pattern::Like("back\\\\slash!%".into())
+        );
+
+        // the seed's regex is used as is, while senders' terms are escaped
+        let regex = Nick::from("kks(_|\\|away)?");
+        assert_eq!(
+            regex.query(&mode(MatchMode::Regex)),
+            Pattern::Regex("^(?:kks(_|\\|away)?)!".into())
+        );
+        let literal = MatchOptions {
+            literal: true,
+            ..mode(MatchMode::Regex)
+        };
+        assert_eq!(
+            Nick::from("kks|away").query(&literal),
+            Pattern::Regex("^(?:kks\\|away)!".into())
+        );
+        assert_eq!(
+            Ident::from("~kks").query(&literal),
+            Pattern::Regex("^[^!]*!~?(?:kks)@".into())
+        );
+        assert_eq!(
+            Host::from("66\\.205\\..*").query(&mode(MatchMode::Regex)),
+            Pattern::Regex("@(?:66\\.205\\..*)$".into())
+        );
+        // addresses go by --subnet whatever the mode, once they're data
+        let address = Host::from("66.205.192.51");
+        assert_eq!(
+            address.query(&literal),
+            Pattern::Like("%66_205_192_51%".into())
+        );
+        assert_eq!(
+            address.query(&mode(MatchMode::Regex)),
+            Pattern::Regex("@(?:66.205.192.51)$".into())
+        );
+
+        assert!(HostMask::from_str("kks.*!*@*").is_err());
+        let seed = HostMask::unchecked("kks.*!~a!b@c@d.*").unwrap();
+        assert_eq!(seed.nick().to_string(), "kks.*");
+        assert_eq!(seed.ident().to_string(), "~a!b@c");
+        assert!(matches!(
+            HostMask::unchecked("kks!@host"),
+            Err(HostMaskError::EmptyIdent)
+        ));
+    }
+
+    #[test]
+    fn test_ident() {
+        let unverified = Ident::from("~bob");
+        let verified = Ident::from("bob");
+        assert!(!unverified.verified() && verified.verified());
+        assert_eq!(unverified.to_string(), "~bob");
+        assert_eq!(verified.to_string(), "bob");
+        assert_ne!(unverified, verified);
+
+        // either finds both, but no longer anything merely containing `bob`
+        for ident in [&unverified, &verified] {
+            assert_eq!(shown(ident.patterns(&FUZZY)), ["%!bob@%", "%!~bob@%"]);
+        }
+
+        let mask = |s| HostMask::from_str(s).unwrap();
+        assert_eq!(verified.weight(&mask("a!bob@c")), 1.0);
+        assert_eq!(verified.weight(&mask("a!~bob@c")), 0.5);
+        assert_eq!(unverified.weight(&mask("a!bob@c")), 0.5);
+
+        for generic in ["~user", "~sid12345", "~quassel", "uid42", "~ZNC"] {
+            assert!(Ident::from(generic).is_generic(), "{generic}");
+        }
+        for specific in ["~bob", "~sid", "~sidney", "~uid12a", "~42cdc033"] {
+            assert!(!Ident::from(specific).is_generic(), "{specific}");
+        }
+    }
+
+    #[test]
+    fn test_family() {
+        assert_eq!(Nick::from("Kks[Away]\\").casefold(), "kks{away}|");
+        assert_eq!(Nick::from("Kks[m]").to_string(), "Kks[m]");
+        for nick in [
+            "Foo",
+            "foo",
+            "foo_",
+            "foo__",
+            "foo^",
+            "foo|away",
+            "Foo[m]",
+            "FOO\\away",
+            "foo_|zzz",
+        ] {
+            assert_eq!(Nick::from(nick).family(false).to_string(), "foo", "{nick}");
+        }
+        assert_eq!(Nick::from("foo2").family(false).to_string(), "foo2");
+        assert_eq!(Nick::from("foo2_").family(true).to_string(), "foo");
+        assert_eq!(Nick::from("foo_2").family(true).to_string(), "foo");
+        assert_eq!(Nick::from("[foo]").family(false).to_string(), "{foo}");
+        // nothing is stripped down to nothing
+        assert_eq!(Nick::from("__").family(false).to_string(), "__");
+        assert_eq!(Nick::from("|away").family(false).to_string(), "|away");
+        assert_ne!(
+            Nick::from("foo2").family(true),
+            Nick::from("foo").family(false)
+        );
+
+        let family = Nick::from("[Foo]").family(false);
+        assert_eq!(family.query(&FUZZY).to_string(), "ILIKE _foo_%!%");
+        let mask = |s| HostMask::from_str(s).unwrap();
+        assert!(family.matches(&mask("{FOO}|away!a@b"), &FUZZY));
+        assert!(!family.matches(&mask("{foo}bar!a@b"), &FUZZY));
+        assert!(Nick::from("*").family(false).is_wildcard());
+    }
+
+    #[test]
+    fn test_account() {
+        let account = |host| Host::from(host).account().map(|a| a.to_string());
+        assert_eq!(account("user/kks").as_deref(), Some("kks"));
+        assert_eq!(account("user/kks/x-1234").as_deref(), Some("kks"));
+        assert_eq!(account("unaffiliated/kks").as_deref(), Some("kks"));
+        assert_eq!(account("gateway/tor-sasl/kks").as_deref(), Some("kks"));
+        assert_eq!(account("libera/staff/kks"), None);
+        assert_eq!(account("gateway/web/irccloud.com/x-abc"), None);
+        assert_eq!(account("66.205.192.51"), None);
+        assert_eq!(account("user/"), None);
+
+        let kks = Host::from("user/k.ks").account().unwrap();
+        assert_eq!(
+            kks.query(&MatchOptions::default()).to_string(),
+            "~* @(?:user|unaffiliated|gateway/tor-sasl)/k\\.ks(?:/.*)?$"
+        );
+        assert_eq!(
+            Realname::from("50% off_").query(&MatchOptions::default()),
+            Pattern::Like("50\\% off\\_".into())
+        );
+        assert_eq!(Avatar::from("").column(), Column::Avatar);
+        assert!(Avatar::from(" ").is_wildcard());
+    }
+
+    #[test]
+    fn test_host_kind() {
+        for (host, kind, address) in [
+            ("user/kks", HostKind::Account, None),
+            ("user/x-10-0-0-1", HostKind::Account, None),
+            ("unaffiliated/kks", HostKind::Account, None),
+            ("libera/staff/kks", HostKind::Project, None),
+            ("ubuntu/member/kks", HostKind::Project, None),
+            (
+                "gateway/web/cgi-irc/kiwiirc.com/ip.1.2.3.4",
+                HostKind::WebGateway,
+                "1.2.3.4".parse().ok(),
+            ),
+            ("gateway/web/irccloud.com/x-abc", HostKind::WebGateway, None),
+            ("gateway/web/session", HostKind::SharedBouncer, None),
+            ("gateway/tor-sasl/kks", HostKind::Tor, None),
+            ("gateway/shell/matrix.org/x-abc", HostKind::Bridge, None),
+            (
+                "gateway/vpn/example/ip.1.2.3.4",
+                HostKind::WebGateway,
+                "1.2.3.4".parse().ok(),
+            ),
+            ("gateway/vpn/protonvpn/kks", HostKind::SharedBouncer, None),
+            (
+                "66.205.192.51",
+                HostKind::Rdns,
+                "66.205.192.51".parse().ok(),
+            ),
+            ("oftc.example.net", HostKind::Rdns, None),
+        ] {
+            let parsed = Host::from(host);
+            assert_eq!((parsed.2, parsed.1), (kind, address), "{host}");
+            assert_eq!(parsed.expands(), kind != HostKind::SharedBouncer, "{host}");
         }
+        // names are matched whole, so `user/kks` doesn't turn up `user/kks2`
+        assert_eq!(
+            Host::from("gateway/web/irccloud.com/x-abc")
+                .query(&FUZZY)
+                .to_string(),
+            "%@gateway/web/irccloud.com/x-abc"
+        );
+        assert_eq!(
+            Host::from("example.net").query(&FUZZY).to_string(),
+            "%@%.example.net"
+        );
+        // and domains by label, only ever reaching other reverse DNS names
+        let domain = Host::from("example.org");
+        assert_eq!(
+            shown(domain.patterns(&FUZZY)),
+            ["%@%.example.org", "%@example.org"]
+        );
+        assert!(domain.matches(&mask("a.example.org"), &FUZZY));
+        assert!(!domain.matches(&mask("gateway/shell/a.example.org"), &FUZZY));
+        assert!(!domain.matches(&mask("example/staff/a.example.org"), &FUZZY));
+    }
+
+    #[test]
+    fn test_embedded_address() {
+        let ip = "66.205.192.51".parse().ok();
+        assert_eq!(Ident::from("~42cdc033").address(), ip);
+        assert_eq!(Ident::from("42CDC033").address(), ip);
+        assert_eq!(Ident::from("~kks").address(), None);
+        assert_eq!(Ident::from("~42cdc03").address(), None);
+        assert_eq!(Ident::from("~42cdc033a").address(), None);
+        assert_eq!(Host::from("gateway/web/thelounge/ip.66.205.192.51").1, ip);
+        assert_eq!(
+            Host::from("gateway/web/cgi-irc/kiwiirc.com/ip.2001:db8::1").1,
+            "2001:db8::1".parse().ok()
+        );
+
+        // an address is looked for in hosts and in either case of hex ident
+        let host = Host::from(ip.unwrap());
+        assert_eq!(
+            shown(host.patterns(&FUZZY)),
+            ["%66_205_192_51%", "%42cdc033@%", "%42CDC033@%"]
+        );
+        assert_eq!(
+            shown(host.patterns(&subnet(16, 64))),
+            ["%66_205_%", "%42cd____@%", "%42CD____@%"]
+        );
+        let webchat = HostMask::from_str("web!~42cdc0ff@gateway/web/session").unwrap();
+        assert!(!host.matches(&webchat, &FUZZY));
+        assert!(host.matches(&webchat, &subnet(24, 64)));
+        let mibbit = HostMask::from_str("web!~42cdc033@webchat.mibbit.com").unwrap();
+        assert_eq!(mibbit.web_address(), ip);
+        // anywhere else hex idents are just names
+        let cloaked = HostMask::from_str("foo!~deadbeef@user/foo").unwrap();
+        assert_eq!(cloaked.web_address(), None);
+        let direct = HostMask::from_str("web!~42cdc033@example.net").unwrap();
+        assert_eq!(direct.web_address(), None);
+        assert!(!host.matches(&direct, &FUZZY));
+        assert_eq!(
+            shown(Host::from("user/kks").patterns(&FUZZY)),
+            ["%@user/kks"]
+        );
     }
 }
@@ -1,40 +1,79 @@
-use crate::hostmask::{Host, HostMask, Ident, Nick, Query};
-use clap::Parser;
-use futures_util::{stream, StreamExt};
-use log::{debug, info};
+use crate::config::{ConfigFile, DatabaseArgs, MatchModes};
+use crate::export::{Format, JsonNode};
+use crate::graph::{Graph, NodeId};
+use crate::hostmask::{Avatar, Column, Host, HostMask, MatchMode, MatchOptions, Realname, Subnet};
+use crate::source::{MemorySource, SenderSource};
+use crate::stoplist::Stoplist;
+use crate::traversal::{Channel, Limits, Sender, Traversal, Window};
+use clap::{Parser, ValueEnum};
+use itertools::Itertools;
+use log::{debug, error, info, warn};
 use ratatui::crossterm::event;
 use ratatui::crossterm::event::{poll, KeyCode, KeyEventKind};
-use ratatui::prelude::{Color, Constraint, Style, Stylize};
-use ratatui::widgets::{Block, Row as TableRow, Table, TableState};
+use ratatui::prelude::{Color, Constraint, Layout, Line, Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row as TableRow, Table, TableState};
 use ratatui::DefaultTerminal;
-use std::collections::HashSet;
-use std::io;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::str::FromStr;
-use std::time::Duration;
-use itertools::Itertools;
+use std::time::{Duration, SystemTime};
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::sync::oneshot::Receiver;
 use tokio::time::sleep;
-use tokio_postgres::{Client, NoTls, Row};
 use tui_logger::{TuiLoggerLevelOutput, TuiLoggerWidget};
 
+mod config;
+mod date;
+mod export;
+mod graph;
 mod hostmask;
 mod postgres;
+mod source;
+mod stoplist;
+mod tls;
+mod traversal;
 
-fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::Result<()> {
-    let mut senders: Option<HashSet<Sender>> = None;
+fn run(mut terminal: DefaultTerminal, mut rx: Receiver<Graph>) -> io::Result<()> {
+    // the graph, and its discovered senders in display order
+    let mut results: Option<(Graph, Vec<NodeId>)> = None;
+    let mut sort = SortBy::default();
     let mut table_state = TableState::default();
+    // the channel overlap pane, shown with `c`, keeps its selection in step
+    let mut overlap_state: Option<TableState> = None;
     loop {
         terminal.draw(|frame| {
-            if let Some(senders) = &mut senders {
-                let rows = senders
+            if let Some((graph, order)) = &results {
+                let rows = order
                     .iter()
-                    .sorted_unstable_by(|a, b| Ord::cmp(&a.sender.host(), &b.sender.host()))
-                    .map(|sender| {
+                    .map(|&node| {
+                        let sender = graph.sender(node);
+                        let (via, depth) = graph
+                            .found_by(node)
+                            .map(|edge| (edge.dimension, edge.depth.to_string()))
+                            .unwrap_or_default();
+                        let activity = sender.activity.as_ref();
                         TableRow::new([
                             sender.sender.nick().to_string(),
                             sender.sender.ident().to_string(),
                             sender.sender.host().to_string(),
+                            sender.realname.clone().unwrap_or_default(),
+                            sender.networks.join(","),
+                            activity
+                                .map(|activity| date::date(activity.first_seen))
+                                .unwrap_or_default(),
+                            activity
+                                .map(|activity| date::date(activity.last_seen))
+                                .unwrap_or_default(),
+                            activity
+                                .map(|activity| activity.messages.to_string())
+                                .unwrap_or_default(),
+                            via.to_string(),
+                            depth,
                         ])
                     })
                     .collect::<Vec<_>>();
@@ -44,6 +83,13 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
                     Constraint::Fill(1),
                     Constraint::Fill(1),
                     Constraint::Fill(1),
+                    Constraint::Fill(1),
+                    Constraint::Length(10),
+                    Constraint::Length(12),
+                    Constraint::Length(12),
+                    Constraint::Length(6),
+                    Constraint::Length(11),
+                    Constraint::Length(5),
                 ];
                 let table = Table::new(rows, widths)
                     // ...and they can be separated by a fixed spacing.
@@ -52,7 +98,7 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
                     .style(Style::new().blue())
                     // It has an optional header, which is simply a Row always visible at the top.
                     .header(
-                        TableRow::new(vec!["Nick", "Ident", "Host"])
+                        TableRow::new(sort.header())
                             .style(Style::new().bold())
                             // To add space between the header and the rest of the rows, specify the margin
                             .bottom_margin(1),
@@ -60,12 +106,53 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
                     // // It has an optional footer, which is simply a Row always visible at the bottom.
                     // .footer(TableRow::new(vec!["Updated on Dec 28"]))
                     // As any other widget, a Table can be wrapped in a Block.
-                    .block(Block::new().title(format!("{} query results", senders.len())))
+                    .block(Block::new().title(format!(
+                        "{} query results, {} links; s to sort, c for channels",
+                        graph.len(),
+                        graph.edges().len()
+                    )))
                     // The selected row and its content can also be styled.
                     .highlight_style(Style::new().reversed())
                     // ...and potentially show a symbol in front of the selection.
                     .highlight_symbol(">>");
-                frame.render_stateful_widget(table, frame.area(), &mut table_state);
+
+                let selected = table_state.selected().and_then(|i| order.get(i));
+                let chain = selected.map(|&node| provenance(graph, node));
+                let suppressed = graph
+                    .suppressed()
+                    .iter()
+                    .map(|s| Line::from(format!("{} {}: {}", s.dimension, s.term, s.reason)))
+                    .collect::<Vec<_>>();
+                let overlap = overlap_state.is_some().then(|| overlap(graph, order));
+                let [table_area, overlap_area, chain_area, suppressed_area] = Layout::vertical([
+                    Constraint::Fill(1),
+                    Constraint::Length(match &overlap {
+                        Some(_) => (order.len() as u16 + 4).min(12),
+                        None => 0,
+                    }),
+                    Constraint::Length(chain.as_ref().map_or(0, |chain| chain.len() as u16 + 2)),
+                    Constraint::Length(match suppressed.len() {
+                        0 => 0,
+                        n => n.min(5) as u16 + 2,
+                    }),
+                ])
+                .areas(frame.area());
+                frame.render_stateful_widget(table, table_area, &mut table_state);
+                if let (Some(overlap), Some(state)) = (overlap, &mut overlap_state) {
+                    state.select(table_state.selected());
+                    frame.render_stateful_widget(overlap, overlap_area, state);
+                }
+                if let Some(chain) = chain {
+                    let chain = Paragraph::new(chain)
+                        .block(Block::bordered().title("How this sender was found"));
+                    frame.render_widget(chain, chain_area);
+                }
+                if !suppressed.is_empty() {
+                    let title = format!("Terms not followed ({})", suppressed.len());
+                    let suppressed =
+                        Paragraph::new(suppressed).block(Block::bordered().title(title));
+                    frame.render_widget(suppressed, suppressed_area);
+                }
             } else {
                 let logger = TuiLoggerWidget::default()
                     .block(Block::bordered().title("Logs"))
@@ -85,13 +172,24 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
                 if key.kind == KeyEventKind::Press {
                     match key.code {
                         KeyCode::Char('q') => return Ok(()),
+                        KeyCode::Char('c') => {
+                            overlap_state = match overlap_state {
+                                Some(_) => None,
+                                None => Some(TableState::default()),
+                            };
+                        }
+                        KeyCode::Char('s') => {
+                            sort = sort.next();
+                            if let Some((graph, order)) = &mut results {
+                                *order = sort.order(graph);
+                            }
+                        }
                         KeyCode::Up => {
                             table_state.select(Some(
                                 table_state
                                     .selected()
                                     .map(|index| index.saturating_sub(1))
-                                    .unwrap_or(0)
-                                    .max(0),
+                                    .unwrap_or(0),
                             ));
                         }
                         KeyCode::Down => {
@@ -101,7 +199,10 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
                                     .map(|i| i.saturating_add(1))
                                     .unwrap_or(0)
                                     .min(
-                                        senders.as_ref().map(|senders| senders.len()).unwrap_or(0),
+                                        results
+                                            .as_ref()
+                                            .map(|(_, order)| order.len().saturating_sub(1))
+                                            .unwrap_or(0),
                                     ),
                             ));
                         }
@@ -111,219 +212,1381 @@ fn run(mut terminal: DefaultTerminal, mut rx: Receiver<HashSet<Sender>>) -> io::
             }
         }
 
-        if senders.is_none() {
+        if results.is_none() {
             match rx.try_recv() {
-                Ok(res) => {
+                Ok(graph) => {
                     let _ = event::read()?;
-                    senders = Some(res);
+                    let order = sort.order(&graph);
+                    results = Some((graph, order));
                 }
                 Err(TryRecvError::Empty) => continue,
-                Err(TryRecvError::Closed) => panic!("rx closed"),
+                // the worker gave up and logged why; leave the logs up until we quit
+                Err(TryRecvError::Closed) => continue,
             }
         }
     }
 }
 
+/// What the results table is ordered by; `s` cycles through them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    #[default]
+    Host,
+    Nick,
+    FirstSeen,
+    /// most recent first
+    LastSeen,
+    /// most talkative first
+    Messages,
+}
+
+impl SortBy {
+    const HEADER: [&'static str; 10] = [
+        "Nick",
+        "Ident",
+        "Host",
+        "Realname",
+        "Network",
+        "First seen",
+        "Last seen",
+        "Msgs",
+        "Via",
+        "Depth",
+    ];
+
+    fn next(self) -> Self {
+        match self {
+            SortBy::Host => SortBy::Nick,
+            SortBy::Nick => SortBy::FirstSeen,
+            SortBy::FirstSeen => SortBy::LastSeen,
+            SortBy::LastSeen => SortBy::Messages,
+            SortBy::Messages => SortBy::Host,
+        }
+    }
+
+    /// The column headers, with the sorted one marked by which way it runs.
+    fn header(self) -> Vec<String> {
+        let (column, arrow) = match self {
+            SortBy::Nick => (0, "▲"),
+            SortBy::Host => (2, "▲"),
+            SortBy::FirstSeen => (5, "▲"),
+            SortBy::LastSeen => (6, "▼"),
+            SortBy::Messages => (7, "▼"),
+        };
+        Self::HEADER
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                if i == column {
+                    format!("{name} {arrow}")
+                } else {
+                    name.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// `graph`'s discovered senders in this order, with those the backlog
+    /// knows nothing about last.
+    fn order(self, graph: &Graph) -> Vec<NodeId> {
+        let mut senders = graph.discovered().collect::<Vec<_>>();
+        match self {
+            SortBy::Host => senders.sort_by(|(_, a), (_, b)| a.sender.host().cmp(b.sender.host())),
+            SortBy::Nick => senders.sort_by(|(_, a), (_, b)| a.sender.nick().cmp(b.sender.nick())),
+            SortBy::FirstSeen => senders.sort_by_key(|(_, sender)| {
+                let first_seen = sender.activity.map(|activity| activity.first_seen);
+                (first_seen.is_none(), first_seen)
+            }),
+            SortBy::LastSeen => senders.sort_by_key(|(_, sender)| {
+                let last_seen = sender.activity.map(|activity| activity.last_seen);
+                (last_seen.is_none(), Reverse(last_seen))
+            }),
+            SortBy::Messages => senders.sort_by_key(|(_, sender)| {
+                Reverse(sender.activity.map_or(0, |activity| activity.messages))
+            }),
+        }
+        senders.into_iter().map(|(node, _)| node).collect()
+    }
+}
+
+/// How many of the channels the most senders share [`overlap`] shows.
+const OVERLAP_CHANNELS: usize = 8;
+
+/// Which of the busiest channels each sender in `order` was seen in, one row
+/// per sender in the same order as the results table.
+fn overlap<'a>(graph: &'a Graph, order: &[NodeId]) -> Table<'a> {
+    let mut counts = HashMap::<&Channel, usize>::new();
+    for &node in order {
+        for channel in &graph.sender(node).channels {
+            *counts.entry(channel).or_default() += 1;
+        }
+    }
+    let channels = counts
+        .iter()
+        .sorted_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)))
+        .map(|(&channel, _)| channel)
+        .take(OVERLAP_CHANNELS)
+        .collect::<Vec<_>>();
+    let rows = order.iter().map(|&node| {
+        let sender = graph.sender(node);
+        TableRow::new(
+            std::iter::once(sender.sender.to_string()).chain(channels.iter().map(|channel| {
+                let seen = sender.channels.binary_search(channel).is_ok();
+                if seen { "●" } else { "·" }.to_string()
+            })),
+        )
+    });
+    let widths = std::iter::once(Constraint::Fill(1)).chain(
+        channels
+            .iter()
+            .map(|channel| Constraint::Length(channel.to_string().chars().count() as u16)),
+    );
+    let title = if counts.is_empty() {
+        "Channel overlap: the backlog has no channels for these senders".to_string()
+    } else {
+        format!(
+            "Channel overlap, {} of {} channels",
+            channels.len(),
+            counts.len()
+        )
+    };
+    Table::new(rows, widths)
+        .header(
+            TableRow::new(
+                std::iter::once(String::new()).chain(channels.iter().map(|c| c.to_string())),
+            )
+            .style(Style::new().bold()),
+        )
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().reversed())
+        .highlight_symbol(">>")
+}
+
+/// The discovery chain from the seed to `node`, one line per hop.
+fn provenance(graph: &Graph, node: NodeId) -> Vec<Line<'static>> {
+    let seed = graph.sender(Graph::SEED);
+    std::iter::once(Line::from(format!("{} (seed)", seed.sender)))
+        .chain(graph.chain(node).into_iter().map(|edge| {
+            let weak = if edge.weight < 1.0 {
+                format!(" (weight {})", edge.weight)
+            } else {
+                String::new()
+            };
+            let cross = if edge.cross_network {
+                " across networks"
+            } else {
+                ""
+            };
+            let observed = edge
+                .observed
+                .map(|time| format!(" (observed {})", date::timestamp(time)))
+                .unwrap_or_default();
+            let (from, to) = (graph.sender(edge.from), graph.sender(edge.to));
+            let shared = match from.shared_channels(to) {
+                0 => String::new(),
+                1 => " (1 shared channel)".to_string(),
+                n => format!(" ({n} shared channels)"),
+            };
+            Line::from(format!(
+                " └ {} {} at depth {} → {}{observed}{cross}{weak}{shared}",
+                edge.dimension,
+                edge.pattern,
+                edge.depth,
+                graph.sender(edge.to).sender
+            ))
+        }))
+        .collect()
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Exits 0 when senders were found, 1 when none were, and 2 on errors."
+)]
 struct Args {
-    /// libera style host mask NICK!IDENT@HOST
+    /// libera style host mask NICK!IDENT@HOST; `*` and `?` are wildcards
     mask: String,
     /// for hosts that are also an ip address, do we search the subnet?
     #[clap(short, long)]
     subnet: bool,
-    /// how many iterations to traverse
+    /// how many leading bits of an IPv4 address --subnet compares, e.g. 24 or 16
+    #[clap(long, default_value = "24", value_parser = clap::value_parser!(u8).range(8..=32))]
+    v4_prefix: u8,
+    /// how many leading bits of an IPv6 address --subnet compares, e.g. 64 or 48
+    #[clap(long, default_value = "64", value_parser = clap::value_parser!(u8).range(16..=128))]
+    v6_prefix: u8,
+    /// match whole nicks, idents and hosts instead of prefixes, substrings and
+    /// suffixes, unless --nick-match and friends say otherwise
+    #[clap(short, long)]
+    exact: bool,
+    /// how nicks match; defaults to prefix. With regex, the seed's nick is a
+    /// POSIX regex
+    #[clap(long, value_enum)]
+    nick_match: Option<MatchMode>,
+    /// how idents match, with or without their `~`; defaults to exact
+    #[clap(long, value_enum)]
+    ident_match: Option<MatchMode>,
+    /// how hosts match; defaults to suffix. Cloaks only ever match whole or
+    /// case-insensitively, and addresses by --subnet
+    #[clap(long, value_enum)]
+    host_match: Option<MatchMode>,
+    /// how realnames match; defaults to exact. Realnames are always literal,
+    /// so insensitive or substring make for fuzzier matches
+    #[clap(long, value_enum, requires = "realname")]
+    realname_match: Option<MatchMode>,
+    /// only follow senders the backlog has heard from on or after this
+    /// YYYY-MM-DD date
+    #[clap(long, value_parser = date::parse)]
+    since: Option<SystemTime>,
+    /// only follow senders the backlog has heard from on or before this
+    /// YYYY-MM-DD date
+    #[clap(long, value_parser = date::parse)]
+    until: Option<SystemTime>,
+    /// how many iterations to traverse; 0 keeps going until nothing new is found
     #[clap(short, long, default_value = "3")]
     depth: usize,
+    /// same as --depth 0
+    #[clap(long, conflicts_with = "depth")]
+    until_fixpoint: bool,
+    /// stop traversing once this many senders have been found
+    #[clap(long, default_value = "5000")]
+    max_senders: usize,
+    /// stop traversing rather than run more than this many queries
+    #[clap(long, default_value = "1000")]
+    max_queries: usize,
+    /// don't pivot through a term that turns up more senders than this, like
+    /// a university NAT or a shared bouncer
+    #[clap(long, default_value = "100")]
+    max_fanout: usize,
     /// whether to follow idents,
     #[clap(short, long)]
     ident: bool,
+    /// follow idents that many people share too, like `~user` and irccloud's
+    /// `~sid12345`; others can be added to the config file's stoplist
+    #[clap(long, requires = "ident")]
+    generic_idents: bool,
+    /// also link nicks of the same family, which differ only by case and
+    /// decorations like `_`, `^`, `[m]` and `|away`
+    #[clap(long)]
+    family: bool,
+    /// count trailing digits as decoration too, so `foo2` joins foo's family
+    #[clap(long, requires = "family")]
+    family_digits: bool,
+    /// also link senders sharing a realname, skipping ones clients fill in
+    /// for everyone like `Quassel IRC User`
+    #[clap(long)]
+    realname: bool,
+    /// also link senders sharing an avatar URL, on cores new enough to keep them
+    #[clap(long)]
+    avatar: bool,
+    /// also link every cloak of the same services account, like `user/kks`
+    /// and `unaffiliated/kks/x-1234`
+    #[clap(long)]
+    account: bool,
+    /// also link senders the backlog saw change nick into one another, in
+    /// either direction
+    #[clap(long)]
+    nick_changes: bool,
+    /// only follow senders the backlog has seen on these networks, e.g.
+    /// `libera,oftc`
+    #[clap(long, value_delimiter = ',')]
+    network: Vec<String>,
+    /// weaken links between senders the backlog has in channels, but never in
+    /// the same one
+    #[clap(long)]
+    shared_channels: bool,
+    /// print senders to stdout as they're found instead of opening the TUI;
+    /// implied when stdout isn't a terminal
+    #[clap(long)]
+    no_tui: bool,
+    /// how --no-tui prints each sender
+    #[clap(
+        long,
+        value_enum,
+        default_value = "hostmask",
+        conflicts_with = "output"
+    )]
+    format: Lines,
+    /// write the whole graph in this format instead of opening the TUI
+    #[clap(long, value_enum)]
+    output: Option<Format>,
+    /// where --output goes; defaults to stdout
+    #[clap(short, long, requires = "output")]
+    output_file: Option<PathBuf>,
+    /// traverse a file of hostmasks, one per line, instead of the database
+    #[clap(long)]
+    hostmasks: Option<PathBuf>,
+    /// only follow senders seen in this quassel user's buffers, on cores
+    /// shared by several users
+    #[clap(long, conflicts_with = "hostmasks")]
+    quassel_user: Option<String>,
+    /// search the backlog of every quassel user on a shared core
+    #[clap(long, conflicts_with_all = ["quassel_user", "hostmasks"])]
+    all_quassel_users: bool,
+    #[command(flatten)]
+    database: DatabaseArgs,
+}
+
+/// One sender per line, for `--no-tui`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Lines {
+    /// `nick!ident@host`
+    Hostmask,
+    /// an object with the sender id, mask parts and realname
+    Json,
 }
 
+impl Args {
+    fn headless(&self) -> bool {
+        self.no_tui || self.output.is_some() || !io::stdout().is_terminal()
+    }
+
+    /// `None` when traversing until no new senders turn up
+    fn depth(&self) -> Option<usize> {
+        (!self.until_fixpoint && self.depth > 0).then_some(self.depth)
+    }
+
+    fn window(&self) -> Window {
+        Window {
+            since: self.since,
+            // through the end of the day
+            until: self.until.map(date::next_day),
+        }
+    }
+
+    /// The flags' match modes layered over the config file's.
+    fn match_modes(&self, file: &ConfigFile) -> MatchModes {
+        let exact = self.exact.then_some(MatchMode::Exact);
+        file.matching.merge(MatchModes {
+            nick: self.nick_match.or(exact),
+            ident: self.ident_match.or(exact),
+            host: self.host_match.or(exact),
+            realname: self.realname_match.or(exact),
+        })
+    }
+
+    fn match_options(&self, mode: Option<MatchMode>) -> MatchOptions {
+        MatchOptions {
+            subnet: self.subnet.then_some(Subnet {
+                v4: self.v4_prefix,
+                v6: self.v6_prefix,
+            }),
+            mode,
+            literal: false,
+        }
+    }
+
+    /// The built-in stoplist plus the config file's.
+    fn stoplist(&self, file: &ConfigFile) -> Stoplist {
+        Stoplist::with(&Stoplist {
+            generic_idents: self.generic_idents,
+            ..file.stoplist.clone()
+        })
+    }
+
+    /// The seed mask, whose parts needn't be valid when they're regexes.
+    fn seed(&self, modes: &MatchModes) -> Result<HostMask, String> {
+        HostMask::from_str(&self.mask)
+            .or_else(|e| {
+                if modes.any_regex() {
+                    HostMask::unchecked(&self.mask)
+                } else {
+                    Err(e)
+                }
+            })
+            .map_err(|e| format!("invalid mask {:?}: {e}", self.mask))
+    }
+}
+
+/// Exit status when the traversal worked but found nothing, as with grep.
+const EMPTY: u8 = 1;
+/// Exit status for everything else that went wrong; clap uses it for bad arguments too.
+const FAILED: u8 = 2;
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tui_logger::init_logger(log::LevelFilter::Trace).unwrap();
-    tui_logger::set_default_level(log::LevelFilter::Trace);
-    let (tx, rx) = tokio::sync::oneshot::channel::<_>();
+async fn main() -> ExitCode {
     let args = Args::parse();
+    if !args.headless() {
+        tui_logger::init_logger(log::LevelFilter::Trace).unwrap();
+        tui_logger::set_default_level(log::LevelFilter::Trace);
+        debug!("args: {args:?}");
+        return match tui(args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::from(FAILED)
+            }
+        };
+    }
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     debug!("args: {args:?}");
-    dbg!(&args);
+    match headless(&args).await {
+        Ok(0) => ExitCode::from(EMPTY),
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::from(FAILED)
+        }
+    }
+}
+
+fn tui(args: Args) -> io::Result<()> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<_>();
     run_worker(tx, args);
     let mut terminal = ratatui::init();
     terminal.clear()?;
     let app_result = run(terminal, rx);
     ratatui::restore();
-    Ok(app_result?)
+    app_result
 }
 
-fn run_worker(tx: tokio::sync::oneshot::Sender<HashSet<Sender>>, args: Args) {
-    tokio::task::spawn(async move {
-        info!("Hello, world!");
-        let (client, connection) =
-            tokio_postgres::connect("host=localhost user=quassel dbname=quassel", NoTls)
-                .await
-                .unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-
-        // let mask =
-        // HostMask::from_str("TeXNickAL!~synick@c-69-138-250-10.hsd1.md.comcast.net").unwrap();
-        // let mask = HostMask::from_str("BarlowRidge!~LockHimUp@user/Star2021").unwrap();
-        // let mask = HostMask::from_str("roran!~roran@user/roran").unwrap();
-        // let mask = HostMask::from_str("Felenov!~Felenov@miraheze/Felenov").unwrap();
-        // let mask = HostMask::from_str("Unit640!~Unit640@user/Unit640").unwrap();
-        // let mask = HostMask::from_str("kks!~kks@user/kks").unwrap();
-        let mut mask = HostMask::from_str(&args.mask).unwrap();
-        mask.subnet = args.subnet;
-
-        info!("parsed {mask:?}");
-        let depth = 2;
-        let mut nicks: HashSet<Nick> = HashSet::from([mask.nick().to_owned()]);
-        let mut idents: HashSet<Ident> = HashSet::from([mask.ident().to_owned()]);
-        if !args.ident {
-            idents.clear();
+/// Traverses without touching the terminal, either streaming senders to stdout
+/// as they're found or exporting the whole graph at the end. Returns how many
+/// senders were found.
+async fn headless(args: &Args) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(format) = args.output {
+        let graph = collect(args, |_, _| ControlFlow::Continue(())).await?;
+        match &args.output_file {
+            Some(path) => export::write(&graph, format, &mut BufWriter::new(File::create(path)?))?,
+            None => export::write(&graph, format, &mut io::stdout().lock())?,
         }
-        let mut hosts: HashSet<Host> = HashSet::from([mask.host().to_owned()]);
-        let mut senders: HashSet<Sender> = HashSet::new();
-        for i in 0..depth {
-            if [nicks.is_empty(), idents.is_empty(), hosts.is_empty()]
-                .iter()
-                .all(|empty| *empty)
-            {
-                info!("no new query terms found; ending");
-                break;
+        return Ok(graph.len());
+    }
+    let mut stdout = io::stdout().lock();
+    let mut failed = None;
+    let graph = collect(args, |graph, nodes| {
+        match print(&mut stdout, args.format, graph, nodes) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                failed = Some(e);
+                ControlFlow::Break(())
             }
-            debug!(
-                "starting iteration {i}; {},{},{}",
-                nicks.len(),
-                idents.len(),
-                hosts.len()
-            );
-            let mut res = stream::iter(nicks.into_iter())
-                .filter_map(|query| async { search(&client, Box::new(query)).await.ok() })
-                .map(|rows| {
-                    rows.iter()
-                        .map(Sender::try_from)
-                        .filter_map(|row| {
-                            row.ok()
-                                .and_then(|mut sender| {
-                                    sender.sender.subnet = args.subnet;
-                                    senders.insert(sender.clone()).then_some(sender)
-                                })
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .inspect(|nicks| debug!("found {} nicks", nicks.len()))
-                .collect::<Vec<Vec<_>>>()
-                .await
-                .concat()
-                .into_iter()
-                .collect::<HashSet<_>>();
-            debug!("nicks found: {res:?}");
-
-            if args.ident {
-                let r = stream::iter(idents.into_iter())
-                    .filter_map(|query| async {
-                        // shadowing here forces a move, meaning we don't need to have a move closure
-                        debug!("querying for ident {query:?}");
-                        search(&client, Box::new(query)).await.ok()
-                    })
-                    .map(|rows| {
-                        rows.iter()
-                            .map(Sender::try_from)
-                            .filter_map(|row| {
-                                row.ok()
-                                    .and_then(|mut sender| {
-                                        sender.sender.subnet = args.subnet;
-                                        senders.insert(sender.clone()).then_some(sender)
-                                    })
-                            })
-                            .collect::<Vec<_>>()
-                    })
-                    .inspect(|idents| debug!("found {} idents", idents.len()))
-                    .collect::<Vec<Vec<_>>>()
-                    .await
-                    .concat()
-                    .into_iter()
-                    .collect::<HashSet<_>>();
-                debug!("idents found: {r:?}");
-                res.extend(r);
+        }
+    })
+    .await?;
+    match failed {
+        // whoever was reading has seen enough, e.g. `| head`
+        Some(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(graph.len()),
+        Some(e) => Err(e.into()),
+        None => Ok(graph.len()),
+    }
+}
+
+/// Writes one line per sender in `nodes`.
+fn print(out: &mut impl Write, format: Lines, graph: &Graph, nodes: &[NodeId]) -> io::Result<()> {
+    for &node in nodes {
+        let sender = graph.sender(node);
+        match format {
+            Lines::Hostmask => writeln!(out, "{}", sender.sender)?,
+            Lines::Json => {
+                serde_json::to_writer(&mut *out, &JsonNode::new(node, sender))?;
+                writeln!(out)?;
             }
+        }
+    }
+    out.flush()
+}
 
-            let r = stream::iter(hosts.into_iter())
-                .filter_map(|query| async {
-                    // shadowing here forces a move, meaning we don't need to have a move closure
-                    debug!("querying for host {query:?}");
-                    search(&client, Box::new(query)).await.ok()
-                })
-                .map(|rows| {
-                    rows.iter()
-                        .map(Sender::try_from)
-                        .filter_map(|row| {
-                            row.ok()
-                                .and_then(|mut sender| {
-                                    sender.sender.subnet = args.subnet;
-                                    senders.insert(sender.clone()).then_some(sender)
-                                })
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .inspect(|hosts| debug!("found {} hosts", hosts.len()))
-                .collect::<Vec<Vec<_>>>()
-                .await
-                .concat()
-                .into_iter()
-                .collect::<HashSet<_>>();
-            debug!("hosts found: {r:?}");
-            res.extend(r);
-
-            nicks = HashSet::new();
-            idents = HashSet::new();
-            hosts = HashSet::new();
-            res.into_iter().for_each(|sender| {
-                nicks.insert(sender.sender.nick().clone());
-                idents.insert(sender.sender.ident().clone());
-                hosts.insert(sender.sender.host().clone());
-            });
-            debug!("there are {} total senders", senders.len());
+fn run_worker(tx: tokio::sync::oneshot::Sender<Graph>, args: Args) {
+    tokio::task::spawn(async move {
+        info!("Hello, world!");
+        match collect(&args, |_, _| ControlFlow::Continue(())).await {
+            Ok(senders) => {
+                info!("done; press the any key to continue");
+                sleep(Duration::from_secs(1)).await;
+                let _ = tx.send(senders);
+            }
+            Err(e) => error!("{e}; press q to quit"),
         }
-        info!("done; press the any key to continue");
-        sleep(Duration::from_secs(1)).await;
-        let _ = tx.send(senders);
     });
 }
 
-async fn search(
-    client: &Client,
-    query: Box<dyn Query + Send>,
-) -> Result<Vec<Row>, tokio_postgres::Error> {
-    info!("query: {}", query.query());
-    client
-        .query(
-            "SELECT senderid, sender, realname FROM sender WHERE sender LIKE $1::TEXT",
-            &[&query.query()],
+/// Opens whichever source `args` points at and traverses it from the seed mask,
+/// passing each iteration's new senders to `found`.
+async fn collect<F>(
+    args: &Args,
+    found: F,
+) -> Result<Graph, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnMut(&Graph, &[NodeId]) -> ControlFlow<()>,
+{
+    // let mask =
+    // HostMask::from_str("TeXNickAL!~synick@c-69-138-250-10.hsd1.md.comcast.net").unwrap();
+    // let mask = HostMask::from_str("BarlowRidge!~LockHimUp@user/Star2021").unwrap();
+    // let mask = HostMask::from_str("roran!~roran@user/roran").unwrap();
+    // let mask = HostMask::from_str("Felenov!~Felenov@miraheze/Felenov").unwrap();
+    // let mask = HostMask::from_str("Unit640!~Unit640@user/Unit640").unwrap();
+    // let mask = HostMask::from_str("kks!~kks@user/kks").unwrap();
+    let file = ConfigFile::find(&args.database)?;
+    let modes = args.match_modes(&file);
+    let mask = args.seed(&modes)?;
+    info!("parsed {mask:?}");
+
+    let stoplist = args.stoplist(&file);
+    let senders = if let Some(path) = &args.hostmasks {
+        traverse(
+            &MemorySource::load(path)?,
+            args,
+            &modes,
+            &stoplist,
+            mask,
+            found,
         )
         .await
+    } else {
+        let config = config::resolve(&args.database, &file)?;
+        let mut db = postgres::connect(&config)
+            .await
+            .map_err(|e| format!("cannot connect to the database: {e}"))?;
+        db.scope(args.quassel_user.as_deref(), args.all_quassel_users)
+            .await?;
+        traverse(&db, args, &modes, &stoplist, mask, found).await
+    };
+    if senders.is_empty() {
+        warn!("nothing links to {}", args.mask);
+    }
+    Ok(senders)
 }
 
-#[derive(Debug, Eq, Hash, PartialEq, Clone)]
-struct Sender {
-    id: i64,
-    sender: HostMask,
-    realname: Option<String>,
+/// Expands `mask` through `source` along the pivots selected by `args`,
+/// matching each as `modes` says and skipping terms on `stoplist`.
+async fn traverse<F>(
+    source: &impl SenderSource,
+    args: &Args,
+    modes: &MatchModes,
+    stoplist: &Stoplist,
+    mask: HostMask,
+    found: F,
+) -> Graph
+where
+    F: FnMut(&Graph, &[NodeId]) -> ControlFlow<()>,
+{
+    let mut traversal = Traversal::new(
+        Sender::seed(mask),
+        Limits {
+            depth: args.depth(),
+            max_senders: args.max_senders,
+            max_queries: args.max_queries,
+            max_fanout: args.max_fanout,
+        },
+        stoplist.clone(),
+    )
+    .pivot("nick", args.match_options(modes.nick), |sender: &Sender| {
+        vec![sender.sender.nick().clone()]
+    });
+    if args.family {
+        let digits = args.family_digits;
+        traversal = traversal.pivot("family", MatchOptions::default(), move |sender: &Sender| {
+            vec![sender.sender.nick().family(digits)]
+        });
+    }
+    if args.ident {
+        traversal = traversal.pivot(
+            "ident",
+            args.match_options(modes.ident),
+            |sender: &Sender| vec![sender.sender.ident().clone()],
+        );
+    }
+    if args.shared_channels {
+        traversal = traversal.weigh_channels();
+    }
+    let window = args.window();
+    if !window.is_open() {
+        if source.knows_backlog() {
+            traversal = traversal.within(window);
+        } else {
+            warn!(
+                "this source doesn't know when senders were active; ignoring --since and --until"
+            );
+        }
+    }
+    if !args.network.is_empty() {
+        if source.knows_backlog() {
+            traversal = traversal.on_networks(args.network.clone());
+        } else {
+            warn!("this source doesn't know which networks senders were on; ignoring --network");
+        }
+    }
+    // older cores don't keep every column
+    let available = |wanted: bool, column: Column, what: &str| {
+        let supported = source.supports(column);
+        if wanted && !supported {
+            warn!("this source keeps no {what}, so they can't be pivoted on");
+        }
+        wanted && supported
+    };
+    if available(args.realname, Column::Realname, "realnames") {
+        traversal = traversal.pivot(
+            "realname",
+            args.match_options(modes.realname),
+            |sender: &Sender| {
+                sender
+                    .realname
+                    .as_deref()
+                    .map(Realname::from)
+                    .into_iter()
+                    .collect()
+            },
+        );
+    }
+    if available(args.avatar, Column::Avatar, "avatar URLs") {
+        traversal = traversal.pivot("avatar", MatchOptions::default(), |sender: &Sender| {
+            sender
+                .avatar
+                .as_deref()
+                .map(Avatar::from)
+                .into_iter()
+                .collect()
+        });
+    }
+    if args.nick_changes {
+        if source.knows_backlog() {
+            traversal = traversal.follow_nick_changes();
+        } else {
+            warn!("this source has no backlog to see nick changes in; ignoring --nick-changes");
+        }
+    }
+    if args.account {
+        traversal = traversal.pivot("account", MatchOptions::default(), |sender: &Sender| {
+            sender.sender.host().account().into_iter().collect()
+        });
+    }
+    // shared gateways would link everyone behind them, while a webchat ident
+    // links to wherever else its address turns up
+    traversal = traversal.pivot("host", args.match_options(modes.host), |sender: &Sender| {
+        let host = sender.sender.host();
+        host.expands()
+            .then(|| host.clone())
+            .into_iter()
+            .chain(sender.sender.web_address().map(Host::from))
+            .collect()
+    });
+    traversal.run(source, found).await;
+    traversal.into_graph()
 }
 
-impl TryFrom<&Row> for Sender {
-    type Error = Box<dyn std::error::Error>;
+#[cfg(test)]
+mod test {
+    use crate::config::{ConfigFile, MatchModes};
+    use crate::graph::Graph;
+    use crate::hostmask::{HostMask, MatchMode, Subnet};
+    use crate::source::{MemorySource, SenderSource};
+    use crate::stoplist::Stoplist;
+    use crate::traversal::{Activity, Sender, NICK_CHANGE};
+    use crate::{date, print, traverse, Args, Lines, SortBy};
+    use clap::Parser;
+    use std::ops::ControlFlow;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn args(args: &[&str]) -> Args {
+        Args::try_parse_from(["identity-traverse"].iter().chain(args)).unwrap()
+    }
+
+    fn fixture() -> MemorySource {
+        MemorySource::load(Path::new("tests/fixtures/hostmasks.txt")).unwrap()
+    }
+
+    async fn masks(args: &Args) -> Vec<String> {
+        masks_in(&fixture(), args).await
+    }
+
+    async fn masks_in(source: &impl SenderSource, args: &Args) -> Vec<String> {
+        let modes = args.match_modes(&ConfigFile::default());
+        let mask = args.seed(&modes).unwrap();
+        let stoplist = args.stoplist(&ConfigFile::default());
+        let mut found = traverse(source, args, &modes, &stoplist, mask, |_, _| {
+            ControlFlow::Continue(())
+        })
+        .await
+        .discovered()
+        .map(|(_, sender)| sender.sender.to_string())
+        .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+
+    #[tokio::test]
+    async fn test_traverse() {
+        assert_eq!(
+            masks(&args(&["kks!~kks@user/kks", "--depth", "1"])).await,
+            [
+                "kks!~kks@oftc.example.net",
+                "kks!~kks@user/kks",
+                "kks_!~kks@user/kks",
+                "kks|away!~kks@66.205.192.51",
+            ]
+        );
+        // the oftc host leads nowhere new, but kks|away's address turns up a
+        // webchat session and a gateway user
+        assert_eq!(
+            masks(&args(&["kks!~kks@user/kks", "--until-fixpoint"]))
+                .await
+                .len(),
+            6
+        );
+        // roran's host only shares a prefix with the seed, so nicks alone don't get there
+        assert_eq!(
+            masks(&args(&["roran!~roran@user/roran", "--until-fixpoint"])).await,
+            ["roran!~roran@66.205.192.77", "roran!~roran@user/roran"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_idents() {
+        let found = masks(&args(&["x!~foo@nowhere", "--ident", "--depth", "1"])).await;
+        assert_eq!(
+            found,
+            [
+                "fooXbar!~foo@example.org",
+                "foo_bar!~foo@gateway/web/irccloud.com/x-abc"
+            ]
+        );
+        assert!(masks(&args(&["x!~foo@nowhere", "--depth", "1"]))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_traverse_generic_idents() {
+        let alice = "alice!~user@alice.example";
+        assert_eq!(
+            masks(&args(&[alice, "--ident", "--depth", "1"])).await,
+            [alice]
+        );
+        assert_eq!(
+            masks(&args(&[
+                alice,
+                "--ident",
+                "--generic-idents",
+                "--depth",
+                "1"
+            ]))
+            .await,
+            [alice, "bob!~user@bob.example"]
+        );
+        assert!(Args::try_parse_from(["identity-traverse", alice, "--generic-idents"]).is_err());
+
+        // unverified idents make weak links
+        let args = args(&["x!~foo@nowhere", "--ident", "--depth", "1"]);
+        let mask = args.seed(&MatchModes::default()).unwrap();
+        let stoplist = args.stoplist(&ConfigFile::default());
+        let graph = traverse(
+            &fixture(),
+            &args,
+            &MatchModes::default(),
+            &stoplist,
+            mask,
+            |_, _| ControlFlow::Continue(()),
+        )
+        .await;
+        assert_eq!(graph.edges().len(), 2);
+        assert!(graph.edges().iter().all(|edge| edge.weight == 0.5));
+    }
+
+    #[tokio::test]
+    async fn test_traverse_hubs() {
+        let cloudy = "cloudy!~cloudy@ealing.irccloud.com";
+        // irccloud's hosts are on the built-in stoplist
+        assert_eq!(masks(&args(&[cloudy, "--until-fixpoint"])).await, [cloudy]);
+        let file: ConfigFile = toml::from_str("[stoplist]\nhosts = [\"user/*\"]").unwrap();
+        let args = args(&["kks!~kks@user/kks", "--depth", "1", "--max-fanout", "3"]);
+        let stoplist = args.stoplist(&file);
+        assert!(stoplist.stops_host("ealing.irccloud.com"));
+        let mask = args.seed(&MatchModes::default()).unwrap();
+        let graph = traverse(
+            &fixture(),
+            &args,
+            &MatchModes::default(),
+            &stoplist,
+            mask,
+            |_, _| ControlFlow::Continue(()),
+        )
+        .await;
+        // the cloak is stoplisted and the nick turns up more than three
+        // senders, which are kept but not followed
+        assert_eq!(graph.len(), 4);
+        assert_eq!(
+            graph
+                .suppressed()
+                .iter()
+                .map(|s| format!("{} {}: {}", s.dimension, s.term, s.reason))
+                .collect::<Vec<_>>(),
+            [
+                "host user/kks: on the stoplist",
+                "nick kks: hub of over 3 senders"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_subnet() {
+        let away = "kks|away!~kks@66.205.192.51";
+        let lounge = "lounge!~lounge@gateway/web/thelounge/ip.66.205.192.51";
+        let webkks = "webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session";
+        assert_eq!(
+            masks(&args(&[away, "--until-fixpoint"])).await,
+            [away, lounge, webkks]
+        );
+        // roran's address is in the same /24, and his cloak is a nick away from that
+        assert_eq!(
+            masks(&args(&[away, "--subnet", "--depth", "1"])).await,
+            [away, lounge, "roran!~roran@66.205.192.77", webkks]
+        );
+        assert_eq!(
+            masks(&args(&[away, "--subnet", "--until-fixpoint"])).await,
+            [
+                away,
+                lounge,
+                "roran!~roran@66.205.192.77",
+                "roran!~roran@user/roran",
+                webkks
+            ]
+        );
+        assert_eq!(
+            masks(&args(&[away, "--subnet", "--v4-prefix", "32"])).await,
+            [away, lounge, webkks]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_webchat() {
+        // the session host is shared, but the ident carries kks|away's address
+        assert_eq!(
+            masks(&args(&[
+                "webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session",
+                "--depth",
+                "1"
+            ]))
+            .await,
+            [
+                "kks|away!~kks@66.205.192.51",
+                "lounge!~lounge@gateway/web/thelounge/ip.66.205.192.51",
+                "webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session",
+            ]
+        );
+        // and a later direct connection finds its way back to both
+        assert_eq!(
+            masks(&args(&["someone!~someone@66.205.192.51", "--depth", "1"])).await,
+            [
+                "kks|away!~kks@66.205.192.51",
+                "lounge!~lounge@gateway/web/thelounge/ip.66.205.192.51",
+                "webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session",
+            ]
+        );
+
+        // an ident off a web client is a name, not 222.173.190.239
+        let source = "foo!~deadbeef@user/foo\nbar!~bar@222.173.190.239"
+            .parse::<MemorySource>()
+            .unwrap();
+        let foo = "foo!~deadbeef@user/foo";
+        assert_eq!(
+            masks_in(&source, &args(&[foo, "--depth", "1"])).await,
+            [foo]
+        );
+        let bar = "bar!~bar@222.173.190.239";
+        assert_eq!(
+            masks_in(&source, &args(&[bar, "--depth", "1"])).await,
+            [bar]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_shared_gateway() {
+        // Unit640 also used a shell provider, but so did a stranger
+        assert_eq!(
+            masks(&args(&[
+                "Unit640!~Unit640@user/Unit640",
+                "--until-fixpoint"
+            ]))
+            .await,
+            [
+                "Unit640!~Unit640@gateway/shell/example.org",
+                "Unit640!~Unit640@user/Unit640",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_exact() {
+        assert_eq!(
+            masks(&args(&["kks!~kks@user/kks", "--exact", "--depth", "1"])).await,
+            [
+                "kks!~kks@oftc.example.net",
+                "kks!~kks@user/kks",
+                "kks_!~kks@user/kks",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_match_modes() {
+        // the seed's nick is a regex, which later nicks are matched against whole
+        assert_eq!(
+            masks(&args(&[
+                "kks(_|\\|away)?!*@*",
+                "--nick-match",
+                "regex",
+                "--depth",
+                "1"
+            ]))
+            .await,
+            [
+                "kks!~kks@oftc.example.net",
+                "kks!~kks@user/kks",
+                "kks_!~kks@user/kks",
+                "kks|away!~kks@66.205.192.51",
+            ]
+        );
+        assert!(args(&["kks(!*@*"]).seed(&MatchModes::default()).is_err());
+        // `_` in a nick is no wildcard, so fooXbar only turns up by its ident
+        assert_eq!(
+            masks(&args(&["foo_bar!~x@nowhere", "--depth", "1"])).await,
+            ["foo_bar!~foo@gateway/web/irccloud.com/x-abc"]
+        );
+        assert_eq!(
+            masks(&args(&[
+                "KKS!~kks@nowhere",
+                "--nick-match",
+                "insensitive",
+                "--depth",
+                "1"
+            ]))
+            .await
+            .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let file: ConfigFile =
+            toml::from_str("[match]\nnick = \"suffix\"\nident = \"exact\"").unwrap();
+        assert_eq!(
+            args(&["a!b@c"]).match_modes(&file),
+            MatchModes {
+                nick: Some(MatchMode::Suffix),
+                ident: Some(MatchMode::Exact),
+                host: None,
+                realname: None,
+            }
+        );
+        // flags beat the file, and --exact fills in whatever they leave
+        assert_eq!(
+            args(&["a!b@c", "--exact", "--nick-match", "substring"]).match_modes(&file),
+            MatchModes {
+                nick: Some(MatchMode::Substring),
+                ident: Some(MatchMode::Exact),
+                host: Some(MatchMode::Exact),
+                realname: Some(MatchMode::Exact),
+            }
+        );
+        assert!(
+            Args::try_parse_from(["identity-traverse", "a!b@c", "--host-match", "fuzzy"]).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_family() {
+        let kks = [
+            "KKS[m]!~kks@gateway/shell/matrix.org/x-kks",
+            "kks!~kks@oftc.example.net",
+            "kks!~kks@user/kks",
+            "kks_!~kks@user/kks",
+            "kks|away!~kks@66.205.192.51",
+        ];
+        assert_eq!(
+            masks(&args(&["Kks^!x@nowhere", "--family", "--depth", "1"])).await,
+            kks
+        );
+        // the nick pivot alone is case sensitive
+        assert!(masks(&args(&["Kks^!x@nowhere", "--depth", "1"]))
+            .await
+            .is_empty());
+        // foo_bar and fooXbar share a prefix in any case, but not a family
+        assert!(masks(&args(&["FOO!x@nowhere", "--family", "--depth", "1"]))
+            .await
+            .is_empty());
+        assert!(
+            masks(&args(&["unit!x@nowhere", "--family", "--depth", "1"]))
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            masks(&args(&[
+                "unit!x@nowhere",
+                "--family",
+                "--family-digits",
+                "--depth",
+                "1"
+            ]))
+            .await,
+            [
+                "Unit640!~Unit640@gateway/shell/example.org",
+                "Unit640!~Unit640@user/Unit640",
+            ]
+        );
+        assert!(Args::try_parse_from(["identity-traverse", "a!b@c", "--family-digits"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_traverse_realname() {
+        let without = masks(&args(&["roran!~roran@user/roran", "--depth", "2"])).await;
+        assert_eq!(
+            without,
+            ["roran!~roran@66.205.192.77", "roran!~roran@user/roran"]
+        );
+        // the seed has no realname, so its nick's senders bring it in
+        let found = masks(&args(&[
+            "roran!~roran@user/roran",
+            "--realname",
+            "--depth",
+            "2",
+        ]))
+        .await;
+        assert_eq!(found, without);
+        let found = masks(&args(&[
+            "roran!~roran@user/roran",
+            "--realname",
+            "--realname-match",
+            "insensitive",
+            "--depth",
+            "2",
+        ]))
+        .await;
+        assert!(found.contains(&"rs!~rs@rs.example".to_string()));
+        assert!(
+            masks(&args(&["kks!~kks@user/kks", "--realname", "--depth", "2"]))
+                .await
+                .contains(&"sleeper!~zz@sleeper.example".to_string())
+        );
+        // everyone who never set one shares the client's default
+        assert_eq!(
+            masks(&args(&["newbie!~newbie@newbie.example", "--realname"])).await,
+            ["newbie!~newbie@newbie.example"]
+        );
+        assert!(
+            Args::try_parse_from(["identity-traverse", "a!b@c", "--realname-match", "exact"])
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_traverse_account() {
+        let kks = "kks!~kks@user/kks";
+        let found = masks(&args(&[kks, "--account", "--depth", "1"])).await;
+        assert!(found.contains(&"oldkks!~old@unaffiliated/KKS".to_string()));
+        assert!(found.contains(&"hidden!~hidden@gateway/tor-sasl/kks".to_string()));
+        assert!(!masks(&args(&[kks, "--depth", "1"]))
+            .await
+            .contains(&"oldkks!~old@unaffiliated/KKS".to_string()));
+        // the hostmask file has no avatars, so there's nothing to follow
+        assert_eq!(
+            masks(&args(&[
+                "roran!~roran@user/roran",
+                "--avatar",
+                "--depth",
+                "2"
+            ]))
+            .await,
+            masks(&args(&["roran!~roran@user/roran", "--depth", "2"])).await
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_avatar() {
+        let db = crate::postgres::test::fixture().await;
+        let roran = "roran!~roran@user/roran";
+        assert!(masks_in(&db, &args(&[roran, "--avatar", "--depth", "2"]))
+            .await
+            .contains(&"hammer!~hammer@hammer.example".to_string()));
+        assert!(!masks_in(&db, &args(&[roran, "--depth", "2"]))
+            .await
+            .contains(&"hammer!~hammer@hammer.example".to_string()));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_window() {
+        let db = crate::postgres::test::fixture().await;
+        let kks = "kks!~kks@user/kks";
+        let found = |extra: &'static [&'static str]| {
+            let args = args(&[&[kks, "--depth", "1"][..], extra].concat());
+            let db = &db;
+            async move { masks_in(db, &args).await }
+        };
+        assert_eq!(found(&[]).await.len(), 4);
+        assert_eq!(
+            found(&["--since", "2024-01-01"]).await,
+            ["kks|away!~kks@66.205.192.51"]
+        );
+        // --until takes in the whole day
+        assert_eq!(
+            found(&["--until", "2015-03-01"]).await,
+            ["kks!~kks@user/kks", "kks_!~kks@user/kks"]
+        );
+        assert_eq!(
+            found(&["--since", "2023-01-01", "--until", "2023-01-01"]).await,
+            ["kks!~kks@oftc.example.net"]
+        );
+        // the hostmask file has no backlog to go by
+        assert_eq!(
+            masks(&args(&[kks, "--depth", "1", "--since", "2024-01-01"]))
+                .await
+                .len(),
+            4
+        );
+        assert!(Args::try_parse_from(["identity-traverse", kks, "--since", "June"]).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_network() {
+        let db = crate::postgres::test::fixture().await;
+        let kks = "kks!~kks@user/kks";
+        let found = |extra: &'static [&'static str]| {
+            let args = args(&[&[kks, "--depth", "1"][..], extra].concat());
+            let db = &db;
+            async move { masks_in(db, &args).await }
+        };
+        assert_eq!(
+            found(&["--network", "OFTC"]).await,
+            ["kks!~kks@oftc.example.net"]
+        );
+        assert_eq!(
+            found(&["--network", "libera"]).await,
+            [
+                "kks!~kks@user/kks",
+                "kks_!~kks@user/kks",
+                "kks|away!~kks@66.205.192.51"
+            ]
+        );
+        assert_eq!(found(&["--network", "libera,oftc"]).await.len(), 4);
+        // the hostmask file has no networks to go by
+        assert_eq!(
+            masks(&args(&[kks, "--depth", "1", "--network", "oftc"]))
+                .await
+                .len(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_digit_nicks() {
+        let db = crate::postgres::test::fixture().await;
+        // nicks can't start with a digit, but the core saw these anyway
+        let args = args(&["foo!~pct@escapes.example", "--depth", "1"]);
+        let found = masks_in(&db, &args).await;
+        for mask in ["50%off!~pct@escapes.example", "50xoff!~pct@escapes.example"] {
+            assert!(found.iter().any(|found| found == mask), "{mask}");
+        }
+        assert_eq!(found, masks(&args).await);
+    }
 
-    fn try_from(row: &Row) -> Result<Self, Self::Error> {
-        Ok(Self {
-            id: row.try_get(0)?,
-            sender: HostMask::from_str(row.try_get(1)?)?,
-            realname: None,
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_domain() {
+        let db = crate::postgres::test::fixture().await;
+        // `gateway/shell/example.org` ends in the seed's host, but a shell
+        // shared by anyone isn't under its domain
+        let args = args(&["fooXbar!~foo@example.org", "--depth", "1"]);
+        let found = masks_in(&db, &args).await;
+        for mask in [
+            "Unit640!~Unit640@gateway/shell/example.org",
+            "stranger!~stranger@gateway/shell/example.org",
+        ] {
+            assert!(!found.iter().any(|found| found == mask), "{mask}");
+        }
+        assert_eq!(found, masks(&args).await);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_nick_changes() {
+        let db = crate::postgres::test::fixture().await;
+        let args = args(&["kks!~kks@user/kks", "--depth", "1", "--nick-changes"]);
+        let modes = args.match_modes(&ConfigFile::default());
+        let mask = args.seed(&modes).unwrap();
+        let graph = traverse(&db, &args, &modes, &Stoplist::default(), mask, |_, _| {
+            ControlFlow::Continue(())
         })
+        .await;
+        let change = graph
+            .edges()
+            .iter()
+            .find(|edge| edge.dimension == NICK_CHANGE)
+            .unwrap();
+        assert_eq!(
+            graph.sender(change.to).sender.to_string(),
+            "kks_!~kks@user/kks"
+        );
+        assert_eq!(
+            change.observed.map(date::timestamp).as_deref(),
+            Some("2015-03-01T12:05:00Z")
+        );
+        // the hostmask file has no backlog to see them in
+        assert_eq!(
+            masks(&args).await,
+            masks(&self::args(&["kks!~kks@user/kks", "--depth", "1"])).await
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_traverse_quassel_user() {
+        let mut db = crate::postgres::test::fixture().await;
+        let args = args(&["roran!~roran@user/roran", "--depth", "1"]);
+        db.scope(Some("alice"), false).await.unwrap();
+        assert_eq!(
+            masks_in(&db, &args).await,
+            ["roran!~roran@66.205.192.77", "roran!~roran@user/roran"]
+        );
+        // bob only ever saw roran from home
+        db.scope(Some("bob"), false).await.unwrap();
+        assert_eq!(masks_in(&db, &args).await, ["roran!~roran@66.205.192.77"]);
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut graph = Graph::new(Sender::seed(HostMask::from_str("a!b@c").unwrap()));
+        for (id, mask, activity) in [
+            (1, "zed!~z@a.example", None),
+            (2, "amy!~a@c.example", Some((100, 200, 5))),
+            (3, "bob!~b@b.example", Some((50, 300, 1))),
+        ] {
+            graph.insert(Sender {
+                id: Some(id),
+                activity: activity.map(|(first, last, messages)| Activity {
+                    first_seen: UNIX_EPOCH + Duration::from_secs(first),
+                    last_seen: UNIX_EPOCH + Duration::from_secs(last),
+                    messages,
+                }),
+                ..Sender::seed(HostMask::from_str(mask).unwrap())
+            });
+        }
+        let ids = |sort: SortBy| {
+            sort.order(&graph)
+                .into_iter()
+                .map(|node| graph.sender(node).id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(SortBy::Host), [1, 3, 2]);
+        assert_eq!(ids(SortBy::Nick), [2, 3, 1]);
+        assert_eq!(ids(SortBy::FirstSeen), [3, 2, 1]);
+        assert_eq!(ids(SortBy::LastSeen), [3, 2, 1]);
+        assert_eq!(ids(SortBy::Messages), [2, 3, 1]);
+        assert_eq!(SortBy::Messages.next(), SortBy::Host);
+        assert_eq!(SortBy::LastSeen.header()[6], "Last seen ▼");
+    }
+
+    #[tokio::test]
+    async fn test_print() {
+        let args = args(&["kks!~kks@user/kks", "--depth", "1"]);
+        let modes = MatchModes::default();
+        let mask = args.seed(&modes).unwrap();
+        let mut iterations = Vec::new();
+        let stoplist = Stoplist::default();
+        let graph = traverse(&fixture(), &args, &modes, &stoplist, mask, |_, nodes| {
+            iterations.push(nodes.to_vec());
+            ControlFlow::Continue(())
+        })
+        .await;
+        assert_eq!(iterations.len(), 1);
+
+        let mut out = Vec::new();
+        print(&mut out, Lines::Hostmask, &graph, &iterations[0]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 4);
+        assert!(out
+            .lines()
+            .any(|line| line == "kks|away!~kks@66.205.192.51"));
+
+        let mut out = Vec::new();
+        print(&mut out, Lines::Json, &graph, &iterations[0]).unwrap();
+        for line in String::from_utf8(out).unwrap().lines() {
+            let node: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(node["seed"], false);
+            assert!(node["sender_id"].is_i64());
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                ["identity-traverse", "kks!~kks@user/kks"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+        assert_eq!(parse(&[]).unwrap().depth(), Some(3));
+        assert_eq!(parse(&["--depth", "5"]).unwrap().depth(), Some(5));
+        assert_eq!(parse(&["--depth", "0"]).unwrap().depth(), None);
+        assert_eq!(parse(&["--until-fixpoint"]).unwrap().depth(), None);
+        assert!(parse(&["--depth", "2", "--until-fixpoint"]).is_err());
+        assert!(parse(&["--v4-prefix", "33"]).is_err());
+        assert_eq!(parse(&[]).unwrap().match_options(None).subnet, None);
+        assert_eq!(
+            parse(&["--subnet", "--v6-prefix", "48"])
+                .unwrap()
+                .match_options(None)
+                .subnet,
+            Some(Subnet { v4: 24, v6: 48 })
+        );
+    }
+
+    /// The in-memory fixture mirrors the database one, so both should agree.
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_sources_agree() {
+        let db = crate::postgres::test::fixture().await;
+        for mask in [
+            "kks!~kks@user/kks",
+            "roran!~roran@user/roran",
+            "x!~foo@nowhere",
+            "Unit640!~Unit640@user/Unit640",
+            "webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session",
+        ] {
+            let args = args(&[mask, "--ident", "--until-fixpoint"]);
+            assert_eq!(masks_in(&db, &args).await, masks(&args).await);
+        }
+        for args in [
+            args(&[
+                "kks|away!~kks@66.205.192.51",
+                "--subnet",
+                "--until-fixpoint",
+            ]),
+            args(&[
+                "kks(_|\\|away)?!*@*",
+                "--nick-match",
+                "regex",
+                "--until-fixpoint",
+            ]),
+            args(&[
+                "BACK\\SLASH!~BS@*",
+                "--exact",
+                "--nick-match",
+                "insensitive",
+            ]),
+            args(&["Kks^!x@nowhere", "--family", "--until-fixpoint"]),
+            args(&["cloudy!~cloudy@ealing.irccloud.com", "--until-fixpoint"]),
+            args(&["kks!~kks@user/kks", "--until-fixpoint", "--max-fanout", "3"]),
+            args(&["kks!~kks@user/kks", "--realname", "--until-fixpoint"]),
+            args(&["kks!~kks@user/kks", "--account", "--until-fixpoint"]),
+            args(&[
+                "roran!~roran@user/roran",
+                "--realname",
+                "--realname-match",
+                "substring",
+                "--until-fixpoint",
+            ]),
+        ] {
+            assert_eq!(masks_in(&db, &args).await, masks(&args).await);
+        }
     }
 }
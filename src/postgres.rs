@@ -0,0 +1,879 @@
+use crate::config::ConnectionConfig;
+use crate::hostmask::{Column, HostMask, HostMaskError, MatchOptions, Pattern, Query};
+use crate::source::SenderSource;
+use crate::tls::TlsError;
+use crate::traversal::{Activity, Channel, NickChange, Sender};
+use itertools::Itertools;
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::{Client, NoTls, Row, Statement};
+
+/// Opens a connection, over TLS unless the sslmode is `disable`.
+pub async fn connect(config: &ConnectionConfig) -> Result<Quassel, DatabaseError> {
+    let client = if config.postgres.get_ssl_mode() == SslMode::Disable {
+        let (client, connection) = config.postgres.connect(NoTls).await?;
+        drive(connection);
+        client
+    } else {
+        let (client, connection) = config.postgres.connect(config.tls.connector()?).await?;
+        drive(connection);
+        client
+    };
+    Quassel::new(client).await
+}
+
+/// Drives a connection in the background, logging if it drops.
+fn drive(connection: impl Future<Output = Result<(), tokio_postgres::Error>> + Send + 'static) {
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("connection error: {e}");
+        }
+    });
+}
+
+/// Typed access to the parts of the Quassel schema that we traverse.
+///
+/// All statements are prepared once up front so the traversal, the TUI and
+/// any future commands can share one connection without re-parsing SQL.
+///
+/// Activity, presence and scoping look the backlog up by sender, and nick
+/// changes look it up by message and senders up by mask. On a large core,
+/// make sure indexes serve them, creating `backlog (senderid)`,
+/// `backlog (message) WHERE type = 8` and `sender (sender)` where the core's
+/// schema lacks them; every query is correct without them, just slow.
+pub struct Quassel {
+    client: Client,
+    schema: Schema,
+    /// the quassel user whose backlog every query is confined to, if any
+    scope: Option<QuasselUserRow>,
+    senders_matching: Statement,
+    /// `None` where the schema lacks the column
+    realnames_matching: Option<Statement>,
+    avatars_matching: Option<Statement>,
+    nick_changes: Statement,
+    buffers_by_senders: Statement,
+    user_by_name: Statement,
+    users: Statement,
+}
+
+impl Quassel {
+    pub async fn new(client: Client) -> Result<Self, DatabaseError> {
+        let schema = Schema::detect(&client).await?;
+        match schema.version {
+            Some(version) => info!("quassel schema version {version}"),
+            None => warn!("no quassel schema version in coreinfo"),
+        }
+        let columns = schema.sender_columns();
+        let matching = |column: &str| {
+            format!(
+                "SELECT {columns} FROM sender \
+                 WHERE ({column} LIKE ANY($2::TEXT[]) \
+                 OR {column} ILIKE ANY($3::TEXT[]) \
+                 OR {column} ~* ANY($4::TEXT[])) \
+                 AND {SEEN} LIMIT $5::BIGINT"
+            )
+        };
+        let realnames_matching = if schema.realname {
+            Some(client.prepare(&matching("realname")).await?)
+        } else {
+            None
+        };
+        let avatars_matching = if schema.avatar {
+            Some(client.prepare(&matching("avatarurl")).await?)
+        } else {
+            None
+        };
+        Ok(Self {
+            senders_matching: client.prepare(&matching("sender")).await?,
+            realnames_matching,
+            avatars_matching,
+            nick_changes: client
+                .prepare(&format!(
+                    "WITH changes AS ({NICK_CHANGES}) \
+                     SELECT DISTINCT ON (senderid) {columns}, changes.observed \
+                     FROM changes JOIN sender USING (senderid) \
+                     WHERE {SEEN} ORDER BY senderid, changes.observed"
+                ))
+                .await?,
+            buffers_by_senders: client
+                .prepare(
+                    "SELECT backlog.senderid, network.networkname, \
+                     buffer.buffername, buffer.buffertype, min(backlog.time) AS first_seen, \
+                     max(backlog.time) AS last_seen, count(*) AS messages \
+                     FROM backlog JOIN buffer USING (bufferid) \
+                     JOIN network ON network.networkid = buffer.networkid \
+                     WHERE backlog.senderid = ANY($2::BIGINT[]) \
+                     AND ($1::INTEGER IS NULL OR buffer.userid = $1) \
+                     GROUP BY backlog.senderid, network.networkname, \
+                     buffer.buffername, buffer.buffertype \
+                     ORDER BY backlog.senderid, network.networkname, buffer.buffername",
+                )
+                .await?,
+            user_by_name: client
+                .prepare("SELECT userid, username FROM quasseluser WHERE username = $1::TEXT")
+                .await?,
+            users: client
+                .prepare("SELECT userid, username FROM quasseluser ORDER BY userid")
+                .await?,
+            client,
+            schema,
+            scope: None,
+        })
+    }
+
+    /// Confines every query to the backlog of the quassel user `name`, so
+    /// only senders seen in their buffers turn up. With no `name`, a core
+    /// with more than one user is refused unless `everyone` allows reading
+    /// all of their backlogs.
+    pub async fn scope(&mut self, name: Option<&str>, everyone: bool) -> Result<(), DatabaseError> {
+        let Some(name) = name else {
+            let users = self.users().await?;
+            if users.len() > 1 && !everyone {
+                return Err(DatabaseError::Unscoped(
+                    users.into_iter().map(|user| user.name).collect(),
+                ));
+            }
+            self.scope = None;
+            return Ok(());
+        };
+        let user = self
+            .user(name)
+            .await?
+            .ok_or_else(|| DatabaseError::NoSuchUser(name.to_string()))?;
+        info!("confined to the backlog of quassel user {}", user.name);
+        self.scope = Some(user);
+        Ok(())
+    }
+
+    /// The `$1` every statement over the backlog takes.
+    fn user_id(&self) -> Option<i32> {
+        self.scope.as_ref().map(|user| user.id)
+    }
+
+    /// Senders whose `column` matches any of `patterns`, or none if the
+    /// schema lacks it; at most `limit` of them, if given.
+    pub async fn matching(
+        &self,
+        column: Column,
+        patterns: &[Pattern],
+        limit: Option<usize>,
+    ) -> Result<Vec<SenderRow>, DatabaseError> {
+        let statement = match column {
+            Column::Sender => Some(&self.senders_matching),
+            Column::Realname => self.realnames_matching.as_ref(),
+            Column::Avatar => self.avatars_matching.as_ref(),
+        };
+        let Some(statement) = statement else {
+            return Ok(Vec::new());
+        };
+        let (mut like, mut ilike, mut regex) = (Vec::new(), Vec::new(), Vec::new());
+        for pattern in patterns {
+            match pattern {
+                Pattern::Like(pattern) => like.push(pattern),
+                Pattern::ILike(pattern) => ilike.push(pattern),
+                Pattern::Regex(pattern) => regex.push(pattern),
+            }
+        }
+        // a NULL limit is no limit
+        let limit = limit.map(|limit| i64::try_from(limit).unwrap_or(i64::MAX));
+        self.query(statement, &[&self.user_id(), &like, &ilike, &regex, &limit])
+            .await
+    }
+
+    /// The sender `id`, which only tests look up, so it's prepared on demand.
+    #[cfg(test)]
+    pub async fn sender(&self, id: i64) -> Result<Option<SenderRow>, DatabaseError> {
+        let statement = self
+            .client
+            .prepare(&format!(
+                "SELECT {} FROM sender WHERE senderid = $2::BIGINT AND {SEEN}",
+                self.schema.sender_columns()
+            ))
+            .await?;
+        self.query_opt(&statement, &[&self.user_id(), &id]).await
+    }
+
+    /// The senders on the other side of every nick change the backlog saw
+    /// the sender `mask` make, or make into it, each with when it first did.
+    pub async fn nick_changes_of(&self, mask: &str) -> Result<Vec<NickChangeRow>, DatabaseError> {
+        self.query(&self.nick_changes, &[&self.user_id(), &mask])
+            .await
+    }
+
+    /// Fills in when and where the backlog has each of `senders`: their
+    /// activity, networks and channels, all in one query.
+    async fn place(&self, senders: &mut [Sender]) -> Result<(), DatabaseError> {
+        let ids = senders
+            .iter()
+            .filter_map(|sender| sender.id)
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut presence = HashMap::<i64, Vec<PresenceRow>>::new();
+        for row in self.presence(&ids).await? {
+            presence.entry(row.sender).or_default().push(row);
+        }
+        for sender in senders {
+            let Some(rows) = sender.id.and_then(|id| presence.remove(&id)) else {
+                continue;
+            };
+            sender.activity = Some(Activity {
+                first_seen: rows.iter().map(|row| row.first_seen).min().unwrap(),
+                last_seen: rows.iter().map(|row| row.last_seen).max().unwrap(),
+                messages: rows.iter().map(|row| row.messages).sum(),
+            });
+            // rows come sorted by network, then buffer
+            sender.networks = rows.iter().map(|row| row.network.clone()).dedup().collect();
+            sender.channels = rows
+                .into_iter()
+                .filter(|row| row.kind == CHANNEL_BUFFER)
+                .map(|row| Channel {
+                    network: row.network,
+                    name: row.buffer,
+                })
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Every buffer each of `senders` shows up in, by sender, with when and
+    /// how often they did.
+    pub async fn presence(&self, senders: &[i64]) -> Result<Vec<PresenceRow>, DatabaseError> {
+        self.query(&self.buffers_by_senders, &[&self.user_id(), &senders])
+            .await
+    }
+
+    pub async fn user(&self, name: &str) -> Result<Option<QuasselUserRow>, DatabaseError> {
+        self.query_opt(&self.user_by_name, &[&name]).await
+    }
+
+    pub async fn users(&self) -> Result<Vec<QuasselUserRow>, DatabaseError> {
+        self.query(&self.users, &[]).await
+    }
+
+    async fn query<T: TryFrom<Row, Error = tokio_postgres::Error>>(
+        &self,
+        statement: &Statement,
+        params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+    ) -> Result<Vec<T>, DatabaseError> {
+        self.client
+            .query(statement, params)
+            .await?
+            .into_iter()
+            .map(|row| T::try_from(row).map_err(DatabaseError::from))
+            .collect()
+    }
+
+    async fn query_opt<T: TryFrom<Row, Error = tokio_postgres::Error>>(
+        &self,
+        statement: &Statement,
+        params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+    ) -> Result<Option<T>, DatabaseError> {
+        Ok(self
+            .client
+            .query_opt(statement, params)
+            .await?
+            .map(T::try_from)
+            .transpose()?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+    #[error("postgres error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("tls error: {0}")]
+    Tls(#[from] TlsError),
+    #[error("no quassel user named {0:?}")]
+    NoSuchUser(String),
+    #[error(
+        "this core holds the backlog of several users ({}); pick one with --quassel-user, \
+         or pass --all-quassel-users to search them all",
+        .0.join(", ")
+    )]
+    Unscoped(Vec<String>),
+}
+
+/// Keeps only senders the quassel user `$1` has backlog from, when scoped.
+/// Each sender costs one probe of the backlog, which stops at its first row.
+const SEEN: &str = "($1::INTEGER IS NULL OR EXISTS (\
+    SELECT FROM backlog JOIN buffer USING (bufferid) \
+    WHERE backlog.senderid = sender.senderid AND buffer.userid = $1))";
+
+/// The `senderid` on the other side of each nick change into or out of the
+/// sender `$2`, and when it happened, in the backlog of the quassel user `$1`.
+/// Type 8 is a `NICK` message from the old sender, whose text is the new nick;
+/// the new sender keeps the old one's `!ident@host`. Either way the change
+/// rows are found first, out of `$2` by its sender and into it by its nick,
+/// and only the senders on those rows are compared against `$2`.
+const NICK_CHANGES: &str = "\
+    SELECT new.senderid, change.time AS observed \
+    FROM backlog change JOIN buffer USING (bufferid) \
+    JOIN sender old ON old.senderid = change.senderid \
+    JOIN sender new ON new.sender = change.message || substring(old.sender FROM '!.*$') \
+    WHERE change.type = 8 AND old.sender = $2::TEXT \
+    AND ($1::INTEGER IS NULL OR buffer.userid = $1) \
+    UNION ALL \
+    SELECT old.senderid, change.time \
+    FROM backlog change JOIN buffer USING (bufferid) \
+    JOIN sender old ON old.senderid = change.senderid \
+    WHERE change.type = 8 AND change.message = substring($2::TEXT FROM '^[^!]*') \
+    AND change.message || substring(old.sender FROM '!.*$') = $2::TEXT \
+    AND ($1::INTEGER IS NULL OR buffer.userid = $1)";
+
+/// Which version of Quassel's schema the core is on, and so which of the
+/// `sender` columns added over the years it has.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Schema {
+    /// `schemaversion` from `coreinfo`
+    pub version: Option<i32>,
+    pub realname: bool,
+    pub avatar: bool,
+}
+
+impl Schema {
+    /// Reads the version, and checks for the columns themselves rather than
+    /// trusting it to say which exist.
+    async fn detect(client: &Client) -> Result<Self, DatabaseError> {
+        let version = client
+            .query_opt(
+                "SELECT value FROM coreinfo WHERE key = 'schemaversion'",
+                &[],
+            )
+            .await?
+            .and_then(|row| row.get::<_, Option<String>>("value"))
+            .and_then(|version| version.parse().ok());
+        let columns = client
+            .query(
+                "SELECT column_name::TEXT FROM information_schema.columns \
+                 WHERE table_schema = current_schema() AND table_name = 'sender'",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .collect::<Vec<_>>();
+        let has = |column: &str| columns.iter().any(|c| c == column);
+        Ok(Self {
+            version,
+            realname: has("realname"),
+            avatar: has("avatarurl"),
+        })
+    }
+
+    /// The `sender` columns to select, with `NULL` standing in for any this
+    /// schema lacks.
+    fn sender_columns(&self) -> String {
+        let or_null = |present: bool, column: &str| {
+            if present {
+                column.to_string()
+            } else {
+                format!("NULL::TEXT AS {column}")
+            }
+        };
+        format!(
+            "senderid, sender, {}, {}",
+            or_null(self.realname, "realname"),
+            or_null(self.avatar, "avatarurl")
+        )
+    }
+}
+
+/// A row of the `sender` table; `sender` is the raw `nick!ident@host` string.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SenderRow {
+    pub id: i64,
+    pub sender: String,
+    pub realname: Option<String>,
+    pub avatar: Option<String>,
+}
+
+impl TryFrom<Row> for SenderRow {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.try_get("senderid")?,
+            sender: row.try_get("sender")?,
+            realname: row.try_get("realname")?,
+            avatar: row.try_get("avatarurl")?,
+        })
+    }
+}
+
+impl SenderSource for Quassel {
+    type Error = DatabaseError;
+
+    async fn lookup(
+        &self,
+        query: &(dyn Query + Send + Sync),
+        options: &MatchOptions,
+        limit: Option<usize>,
+    ) -> Result<Vec<Sender>, DatabaseError> {
+        let mut senders = self
+            .matching(query.column(), &query.patterns(options), limit)
+            .await?
+            .into_iter()
+            .filter_map(|row| {
+                Sender::try_from(&row)
+                    .inspect_err(|e| debug!("skipping sender {:?}: {e}", row.sender))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        self.place(&mut senders).await?;
+        Ok(senders)
+    }
+
+    fn supports(&self, column: Column) -> bool {
+        match column {
+            Column::Sender => true,
+            Column::Realname => self.schema.realname,
+            Column::Avatar => self.schema.avatar,
+        }
+    }
+
+    fn knows_backlog(&self) -> bool {
+        true
+    }
+
+    async fn nick_changes(&self, sender: &Sender) -> Result<Vec<NickChange>, DatabaseError> {
+        let mut changes = self
+            .nick_changes_of(&sender.sender.to_string())
+            .await?
+            .into_iter()
+            .filter_map(|row| {
+                Some(NickChange {
+                    sender: Sender::try_from(&row.sender)
+                        .inspect_err(|e| debug!("skipping sender {:?}: {e}", row.sender.sender))
+                        .ok()?,
+                    observed: row.observed,
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut senders = changes
+            .iter()
+            .map(|change| change.sender.clone())
+            .collect::<Vec<_>>();
+        self.place(&mut senders).await?;
+        for (change, sender) in changes.iter_mut().zip(senders) {
+            change.sender = sender;
+        }
+        Ok(changes)
+    }
+}
+
+impl TryFrom<&SenderRow> for Sender {
+    type Error = HostMaskError;
+
+    fn try_from(row: &SenderRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: Some(row.id),
+            // the core saw it, so it's real whether or not it looks valid
+            sender: HostMask::unchecked(&row.sender)?,
+            realname: row.realname.clone(),
+            avatar: row.avatar.clone(),
+            // filled in by the lookup, which asks for every sender's at once
+            activity: None,
+            networks: Vec::new(),
+            channels: Vec::new(),
+        })
+    }
+}
+
+/// A sender on the other side of a nick change, and when the backlog first
+/// saw it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NickChangeRow {
+    pub sender: SenderRow,
+    pub observed: SystemTime,
+}
+
+impl TryFrom<Row> for NickChangeRow {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        let observed = row.try_get("observed")?;
+        Ok(Self {
+            sender: SenderRow::try_from(row)?,
+            observed,
+        })
+    }
+}
+
+/// `buffertype` of a channel, rather than a status or query buffer.
+const CHANNEL_BUFFER: i32 = 2;
+
+/// A buffer a sender shows up in, named along with its network.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PresenceRow {
+    pub sender: i64,
+    pub network: String,
+    pub buffer: String,
+    pub kind: i32,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub messages: i64,
+}
+
+impl TryFrom<Row> for PresenceRow {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            sender: row.try_get("senderid")?,
+            network: row.try_get("networkname")?,
+            buffer: row.try_get("buffername")?,
+            kind: row.try_get("buffertype")?,
+            first_seen: row.try_get("first_seen")?,
+            last_seen: row.try_get("last_seen")?,
+            messages: row.try_get("messages")?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct QuasselUserRow {
+    pub id: i32,
+    pub name: String,
+}
+
+impl TryFrom<Row> for QuasselUserRow {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.try_get("userid")?,
+            name: row.try_get("username")?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{DatabaseError, Quassel, Schema};
+    use crate::date;
+    use crate::hostmask::{
+        Avatar, Column, MatchMode, MatchOptions, Nick, Pattern, Query, Realname,
+    };
+    use crate::source::SenderSource;
+    use crate::traversal::Sender;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio_postgres::NoTls;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/quassel.sql");
+
+    /// Connects to `IDENTITY_TRAVERSE_TEST_DATABASE` and loads the fixture into
+    /// a fresh schema so concurrent tests don't trample each other.
+    pub(crate) async fn fixture() -> Quassel {
+        fixture_with("").await
+    }
+
+    /// The fixture, with `sql` run over it before connecting, e.g. to take it
+    /// back to an older schema.
+    async fn fixture_with(sql: &str) -> Quassel {
+        static SCHEMA: AtomicUsize = AtomicUsize::new(0);
+        let config = std::env::var("IDENTITY_TRAVERSE_TEST_DATABASE")
+            .expect("IDENTITY_TRAVERSE_TEST_DATABASE must point at a scratch database");
+        let (client, connection) = tokio_postgres::connect(&config, NoTls).await.unwrap();
+        tokio::spawn(connection);
+        let schema = format!(
+            "identity_traverse_{}_{}",
+            std::process::id(),
+            SCHEMA.fetch_add(1, Ordering::Relaxed)
+        );
+        client
+            .batch_execute(&format!(
+                "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}; SET search_path TO {schema};"
+            ))
+            .await
+            .unwrap();
+        client.batch_execute(FIXTURE).await.unwrap();
+        client.batch_execute(sql).await.unwrap();
+        Quassel::new(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_senders_matching() {
+        let db = fixture().await;
+        let senders = db
+            .matching(Column::Sender, &[Pattern::Like("%@user/kks".into())], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            senders
+                .iter()
+                .map(|s| s.sender.as_str())
+                .collect::<Vec<_>>(),
+            ["kks!~kks@user/kks", "kks_!~kks@user/kks"]
+        );
+        let limited = db
+            .matching(Column::Sender, &[Pattern::Like("%".into())], Some(2))
+            .await
+            .unwrap();
+        assert_eq!(limited.len(), 2);
+        assert_eq!(
+            db.sender(senders[0].id).await.unwrap(),
+            Some(senders[0].clone())
+        );
+        assert_eq!(db.sender(-1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_escaping() {
+        let db = fixture().await;
+        let exact = MatchOptions {
+            mode: Some(MatchMode::Exact),
+            ..Default::default()
+        };
+        // each nick has a twin its metacharacter would match if left unescaped
+        for nick in ["foo_bar", "back\\slash", "50%off"] {
+            let senders = db
+                .matching(Column::Sender, &Nick::from(nick).patterns(&exact), None)
+                .await
+                .unwrap();
+            assert_eq!(senders.len(), 1, "{nick}");
+            assert!(senders[0].sender.starts_with(&format!("{nick}!")));
+        }
+        let insensitive = MatchOptions {
+            mode: Some(MatchMode::Insensitive),
+            ..Default::default()
+        };
+        let senders = db
+            .matching(
+                Column::Sender,
+                &Nick::from("50%OFF").patterns(&insensitive),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(senders.len(), 1);
+        let regex = MatchOptions {
+            mode: Some(MatchMode::Regex),
+            ..Default::default()
+        };
+        let senders = db
+            .matching(Column::Sender, &Nick::from("50.off").patterns(&regex), None)
+            .await
+            .unwrap();
+        assert_eq!(senders.len(), 2);
+        let literal = MatchOptions {
+            literal: true,
+            ..regex
+        };
+        let senders = db
+            .matching(
+                Column::Sender,
+                &Nick::from("back\\slash").patterns(&literal),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(senders.len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_realnames() {
+        let db = fixture().await;
+        let found = db
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert!(found
+            .iter()
+            .all(|s| s.realname.as_deref() == Some("Roran Stronghammer")));
+
+        let found = db
+            .lookup(
+                &Realname::from("Roran Stronghammer"),
+                &MatchOptions::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        let insensitive = MatchOptions {
+            mode: Some(MatchMode::Insensitive),
+            ..Default::default()
+        };
+        let found = db
+            .lookup(&Realname::from("Roran Stronghammer"), &insensitive, None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 3);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_schema() {
+        let db = fixture().await;
+        assert_eq!(
+            &db.schema,
+            &Schema {
+                version: Some(31),
+                realname: true,
+                avatar: true,
+            }
+        );
+        let found = db
+            .lookup(
+                &Avatar::from("https://example.com/roran.png"),
+                &MatchOptions::default(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+
+        // cores from before realnames and avatars were kept
+        let db = fixture_with(
+            "ALTER TABLE sender DROP COLUMN realname, DROP COLUMN avatarurl; \
+             UPDATE coreinfo SET value = '16' WHERE key = 'schemaversion';",
+        )
+        .await;
+        assert_eq!(db.schema.version, Some(16));
+        assert!(!db.supports(Column::Realname) && !db.supports(Column::Avatar));
+        let found = db
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|s| s.realname.is_none()));
+        assert!(db
+            .lookup(&Realname::from("kks"), &MatchOptions::default(), None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(db.sender(1).await.unwrap().unwrap().realname, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_activity() {
+        let db = fixture().await;
+        let found = db
+            .lookup(&Nick::from("kks"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        let kks = found.iter().find(|s| s.id == Some(1)).unwrap();
+        let kks = kks.activity.as_ref().unwrap();
+        assert_eq!(kks.messages, 3);
+        assert_eq!(date::timestamp(kks.first_seen), "2015-03-01T12:00:00Z");
+        assert_eq!(date::timestamp(kks.last_seen), "2015-03-01T12:05:00Z");
+
+        // a sender the backlog has nothing from
+        let found = db
+            .lookup(&Nick::from("stranger"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(found[0].id, Some(11));
+        assert_eq!(found[0].activity, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_channels() {
+        let db = fixture().await;
+        let channels = |sender: &Sender| {
+            sender
+                .channels
+                .iter()
+                .map(|channel| channel.to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut found = db
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        found.sort_by_key(|sender| sender.id);
+        // roran was seen in #quassel by both of the core's users
+        assert_eq!(channels(&found[0]), ["libera/#quassel"]);
+        assert_eq!(channels(&found[1]), ["libera/#rust"]);
+        let found = db
+            .lookup(&Nick::from("kks"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        let oftc = found.iter().find(|s| s.id == Some(9)).unwrap();
+        assert_eq!(channels(oftc), ["oftc/#oftc"]);
+        assert_eq!(oftc.networks, ["oftc"]);
+        let found = db
+            .lookup(&Nick::from("stranger"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert!(found[0].channels.is_empty());
+        assert_eq!(db.presence(&[]).await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_scope() {
+        let mut db = fixture().await;
+        // alice and bob share the core, so one of them has to be picked
+        assert!(matches!(
+            db.scope(None, false).await,
+            Err(DatabaseError::Unscoped(users)) if users == ["alice", "bob"]
+        ));
+        db.scope(None, true).await.unwrap();
+        let found = db
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        let roran = found.iter().find(|s| s.id == Some(4)).unwrap();
+        assert_eq!(roran.activity.as_ref().unwrap().messages, 2);
+        assert!(matches!(
+            db.scope(Some("carol"), false).await,
+            Err(DatabaseError::NoSuchUser(_))
+        ));
+
+        db.scope(Some("bob"), false).await.unwrap();
+        let found = db
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        let roran = &found[0];
+        assert_eq!(roran.id, Some(4));
+        assert_eq!(roran.activity.as_ref().unwrap().messages, 1);
+        assert_eq!(roran.networks, ["libera"]);
+        // alice's senders are out of sight
+        assert_eq!(db.sender(1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_nick_changes() {
+        let mut db = fixture().await;
+        let kks = Sender::try_from(&db.sender(1).await.unwrap().unwrap()).unwrap();
+        let kks_ = Sender::try_from(&db.sender(2).await.unwrap().unwrap()).unwrap();
+        // kks became kks_, so each leads to the other
+        let changes = db.nick_changes(&kks).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].sender.sender, kks_.sender);
+        assert_eq!(changes[0].sender.channels.len(), 1);
+        assert_eq!(date::timestamp(changes[0].observed), "2015-03-01T12:05:00Z");
+        let changes = db.nick_changes(&kks_).await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].sender.id, Some(1));
+        let stranger = Sender::try_from(&db.sender(11).await.unwrap().unwrap()).unwrap();
+        assert!(db.nick_changes(&stranger).await.unwrap().is_empty());
+        // bob wasn't there to see it
+        db.scope(Some("bob"), false).await.unwrap();
+        assert!(db.nick_changes(&kks).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_DATABASE"]
+    async fn test_users() {
+        let db = fixture().await;
+        assert_eq!(db.user("alice").await.unwrap().unwrap().id, 1);
+        assert_eq!(db.user("carol").await.unwrap(), None);
+        assert_eq!(db.users().await.unwrap().len(), 2);
+    }
+}
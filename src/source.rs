@@ -0,0 +1,326 @@
+use crate::hostmask::{Column, HostMask, HostMaskError, MatchOptions, Pattern, Query};
+use crate::traversal::{NickChange, Sender};
+use regex::Regex;
+use std::fmt::Display;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fs, io};
+use thiserror::Error;
+
+/// Somewhere to look senders up, e.g. a Quassel database.
+pub trait SenderSource: Sync {
+    type Error: Display + Send;
+
+    /// Every sender whose `nick!ident@host`, or whichever column `query`
+    /// names, matches any pattern of `query`, or any `limit` of them.
+    fn lookup(
+        &self,
+        query: &(dyn Query + Send + Sync),
+        options: &MatchOptions,
+        limit: Option<usize>,
+    ) -> impl Future<Output = Result<Vec<Sender>, Self::Error>> + Send;
+
+    /// Whether there's anything in `column` to look up; older Quassel cores
+    /// don't keep every column.
+    fn supports(&self, _column: Column) -> bool {
+        true
+    }
+
+    /// Whether senders come back with what the backlog has on them, their
+    /// [`Activity`], networks and channels, so senders without any can be
+    /// taken to have never said anything.
+    ///
+    /// [`Activity`]: crate::traversal::Activity
+    fn knows_backlog(&self) -> bool {
+        false
+    }
+
+    /// Every nick change the backlog saw `sender` make, or make into it,
+    /// along with the sender on the other side; none without a backlog.
+    fn nick_changes(
+        &self,
+        _sender: &Sender,
+    ) -> impl Future<Output = Result<Vec<NickChange>, Self::Error>> + Send {
+        std::future::ready(Ok(Vec::new()))
+    }
+}
+
+/// Senders held in memory, for tests and for traversing exported hostmask lists.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySource {
+    senders: Vec<Sender>,
+}
+
+impl MemorySource {
+    /// Reads a fixture file; see [`MemorySource::from_str`] for the format.
+    pub fn load(path: &Path) -> Result<Self, SourceError> {
+        fs::read_to_string(path)
+            .map_err(|e| SourceError::Read(path.into(), e))?
+            .parse()
+    }
+}
+
+impl FromStr for MemorySource {
+    type Err = SourceError;
+
+    /// One `nick!ident@host` per line, optionally followed by whitespace and a
+    /// realname. Blank lines and lines starting with `#` are skipped, and
+    /// senders are numbered from 1 in file order.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let senders = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .enumerate()
+            .map(|(i, line)| {
+                let (mask, realname) = match line.split_once(char::is_whitespace) {
+                    Some((mask, realname)) => (mask, Some(realname.trim().to_string())),
+                    None => (line, None),
+                };
+                Ok(Sender {
+                    id: Some(i as i64 + 1),
+                    sender: HostMask::unchecked(mask)
+                        .map_err(|e| SourceError::Mask(mask.into(), e))?,
+                    realname,
+                    avatar: None,
+                    activity: None,
+                    networks: Vec::new(),
+                    channels: Vec::new(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { senders })
+    }
+}
+
+impl SenderSource for MemorySource {
+    type Error = regex::Error;
+
+    async fn lookup(
+        &self,
+        query: &(dyn Query + Send + Sync),
+        options: &MatchOptions,
+        limit: Option<usize>,
+    ) -> Result<Vec<Sender>, regex::Error> {
+        let patterns = query
+            .patterns(options)
+            .iter()
+            .map(|pattern| match pattern {
+                Pattern::Like(pattern) => Ok(like(pattern)),
+                Pattern::ILike(pattern) => Ok(ilike(pattern)),
+                // close enough to postgres' `~*` for the regexes we build
+                Pattern::Regex(pattern) => Regex::new(&format!("(?i){pattern}")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self
+            .senders
+            .iter()
+            .filter(|sender| {
+                let text = match query.column() {
+                    Column::Sender => Some(sender.sender.to_string()),
+                    Column::Realname => sender.realname.clone(),
+                    Column::Avatar => sender.avatar.clone(),
+                };
+                text.is_some_and(|text| patterns.iter().any(|pattern| pattern.is_match(&text)))
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    /// Fixture files have no avatars.
+    fn supports(&self, column: Column) -> bool {
+        column != Column::Avatar
+    }
+}
+
+/// Translates a postgres LIKE pattern, with its default `\` escape, into a regex.
+fn like(pattern: &str) -> Regex {
+    Regex::new(&translate(pattern)).expect("escaped LIKE patterns are valid regexes")
+}
+
+/// Likewise for ILIKE.
+fn ilike(pattern: &str) -> Regex {
+    Regex::new(&format!("(?i){}", translate(pattern)))
+        .expect("escaped LIKE patterns are valid regexes")
+}
+
+fn translate(pattern: &str) -> String {
+    let mut regex = String::from("(?s)^");
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    regex.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            ch => regex.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("invalid mask {0:?}: {1}")]
+    Mask(String, HostMaskError),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hostmask::{Host, Ident, MatchMode, MatchOptions, Nick, Query, Realname, Subnet};
+    use crate::source::{like, MemorySource, SenderSource};
+    use std::path::Path;
+
+    #[test]
+    fn test_like() {
+        assert!(like("kks%").is_match("kks_!~kks@user/kks"));
+        assert!(!like("kks%").is_match("xkks!~kks@user/kks"));
+        assert!(like("%66_205_192%").is_match("a!b@66.205.192.51"));
+        assert!(like("foo\\_bar%").is_match("foo_bar!~foo@host"));
+        assert!(!like("foo\\_bar%").is_match("fooXbar!~foo@host"));
+        assert!(like("100\\%%").is_match("100%!a@b"));
+        assert!(like("a.b").is_match("a.b") && !like("a.b").is_match("axb"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_source() {
+        let source = MemorySource::load(Path::new("tests/fixtures/hostmasks.txt")).unwrap();
+        let found = source
+            .lookup(&Host::from("user/kks"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|s| s.realname.as_deref() == Some("kks")));
+
+        let found = source
+            .lookup(&Nick::from("roran"), &MatchOptions::default(), None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].realname.as_deref(), Some("Roran Stronghammer"));
+
+        assert!("not a mask".parse::<MemorySource>().is_err());
+    }
+
+    /// The masks of whoever `query` finds in the fixture, matched by `mode`.
+    async fn find(query: &(dyn Query + Send + Sync), mode: MatchMode) -> Vec<String> {
+        let source = MemorySource::load(Path::new("tests/fixtures/hostmasks.txt")).unwrap();
+        let options = MatchOptions {
+            mode: Some(mode),
+            ..Default::default()
+        };
+        let mut found = source
+            .lookup(query, &options, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|sender| sender.sender.to_string())
+            .collect::<Vec<_>>();
+        found.sort();
+        found
+    }
+
+    #[tokio::test]
+    async fn test_v6_prefix() {
+        let source = "near!a@2001:db8:1:2::6\nfar!a@2001:db8:ffff::1\n\
+                      upper!a@2001:DB8:1:2::7\nlocal!a@::1"
+            .parse::<MemorySource>()
+            .unwrap();
+        let options = MatchOptions {
+            subnet: Some(Subnet { v4: 24, v6: 64 }),
+            ..MatchOptions::default()
+        };
+        let found = source
+            .lookup(&Host::from("2001:db8:1:2::5"), &options, None)
+            .await
+            .unwrap();
+        let found = found
+            .iter()
+            .map(|s| s.sender.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["near!a@2001:db8:1:2::6", "upper!a@2001:DB8:1:2::7"]);
+        // a leading zero hextet leaves only the address to look for
+        let found = source
+            .lookup(&Host::from("::1"), &options, None)
+            .await
+            .unwrap();
+        let found = found
+            .iter()
+            .map(|s| s.sender.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["local!a@::1"]);
+    }
+
+    #[tokio::test]
+    async fn test_match_modes() {
+        // `%`, `_` and `\` are literal, not wildcards or escapes
+        assert_eq!(
+            find(&Nick::from("foo_bar"), MatchMode::Exact).await,
+            ["foo_bar!~foo@gateway/web/irccloud.com/x-abc"]
+        );
+        assert_eq!(
+            find(&Nick::from("back\\slash"), MatchMode::Prefix).await,
+            ["back\\slash!~bs@escapes.example"]
+        );
+        assert_eq!(
+            find(&Nick::from("%"), MatchMode::Substring).await,
+            ["50%off!~pct@escapes.example"]
+        );
+        assert_eq!(
+            find(&Nick::from("away"), MatchMode::Suffix).await,
+            ["kks|away!~kks@66.205.192.51"]
+        );
+        assert_eq!(
+            find(&Nick::from("KKS"), MatchMode::Insensitive).await,
+            ["kks!~kks@oftc.example.net", "kks!~kks@user/kks"]
+        );
+        assert_eq!(
+            find(&Nick::from("kks([_|].*)?"), MatchMode::Regex).await,
+            [
+                "kks!~kks@oftc.example.net",
+                "kks!~kks@user/kks",
+                "kks_!~kks@user/kks",
+                "kks|away!~kks@66.205.192.51",
+            ]
+        );
+        // modes apply to their own part of the mask only
+        assert_eq!(
+            find(&Ident::from("~Unit"), MatchMode::Prefix).await.len(),
+            2
+        );
+        assert_eq!(
+            find(&Host::from("Escapes.Example"), MatchMode::Insensitive).await,
+            [
+                "50%off!~pct@escapes.example",
+                "50xoff!~pct@escapes.example",
+                "back\\slash!~bs@escapes.example",
+                "backslash!~bs@escapes.example"
+            ]
+        );
+        // realnames are matched on their own column, literally
+        assert_eq!(
+            find(&Realname::from("Roran Stronghammer"), MatchMode::Exact).await,
+            ["roran!~roran@66.205.192.77", "roran!~roran@user/roran"]
+        );
+        assert_eq!(
+            find(
+                &Realname::from("Roran Stronghammer"),
+                MatchMode::Insensitive
+            )
+            .await
+            .len(),
+            3
+        );
+        assert!(find(&Realname::from("k?s"), MatchMode::Exact)
+            .await
+            .is_empty());
+    }
+}
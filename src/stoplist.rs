@@ -0,0 +1,155 @@
+use serde::Deserialize;
+
+/// Hosts that front for many unrelated people, so never link anyone.
+const HOSTS: &[&str] = &[
+    "*.irccloud.com",
+    "*.kiwiirc.com",
+    "*.mibbit.com",
+    "*.matrix.org",
+    "localhost",
+    "127.0.0.1",
+    "::1",
+];
+
+/// Realnames that clients fill in for everyone who doesn't set their own.
+const REALNAMES: &[&str] = &[
+    "Quassel IRC User",
+    "The Lounge User",
+    "*kiwiirc.com*",
+    "*mibbit.com*",
+    "realname",
+    "real name",
+    "unknown",
+    // any single character, like `*` or `.`
+    "?",
+];
+
+/// Hosts, idents and realnames too widely shared to pivot through, as `*`
+/// and `?` wildcard masks compared case-insensitively. Idents are compared
+/// without their `~`.
+///
+/// ```toml
+/// [stoplist]
+/// hosts = ["nat-*.campus.example.edu"]
+/// idents = ["guest*"]
+/// realnames = ["Campus Guest"]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stoplist {
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub idents: Vec<String>,
+    #[serde(default)]
+    pub realnames: Vec<String>,
+    /// let generic idents like `~user` through; see [`crate::hostmask::Ident::is_generic`]
+    #[serde(skip)]
+    pub generic_idents: bool,
+}
+
+impl Stoplist {
+    /// The built-in list with `extra` added on.
+    pub fn with(extra: &Stoplist) -> Self {
+        Self {
+            hosts: HOSTS
+                .iter()
+                .map(|host| host.to_string())
+                .chain(extra.hosts.iter().cloned())
+                .collect(),
+            idents: extra.idents.clone(),
+            realnames: REALNAMES
+                .iter()
+                .map(|realname| realname.to_string())
+                .chain(extra.realnames.iter().cloned())
+                .collect(),
+            generic_idents: extra.generic_idents,
+        }
+    }
+
+    pub fn stops_host(&self, host: &str) -> bool {
+        self.hosts.iter().any(|mask| glob(mask, host))
+    }
+
+    pub fn stops_ident(&self, ident: &str) -> bool {
+        self.idents.iter().any(|mask| glob(mask, ident))
+    }
+
+    pub fn stops_realname(&self, realname: &str) -> bool {
+        let realname = realname.trim();
+        self.realnames.iter().any(|mask| glob(mask, realname))
+    }
+}
+
+/// Whether `s` matches `mask`, where `*` is any run of characters and `?` any
+/// one, ignoring ASCII case.
+fn glob(mask: &str, s: &str) -> bool {
+    let mask = mask.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    let (mut m, mut i) = (0, 0);
+    // where the last `*` was, and where in `s` it has matched up to
+    let mut star = None;
+    while i < s.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m, i));
+                m += 1;
+            }
+            Some(&ch) if ch == '?' || ch.eq_ignore_ascii_case(&s[i]) => {
+                m += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((star_m, star_i)) => {
+                    star = Some((star_m, star_i + 1));
+                    m = star_m + 1;
+                    i = star_i + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|&ch| ch == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stoplist::{glob, Stoplist};
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("*.irccloud.com", "ealing.IRCCloud.com"));
+        assert!(!glob("*.irccloud.com", "irccloud.com"));
+        assert!(glob("nat-?.example", "nat-1.example"));
+        assert!(!glob("nat-?.example", "nat-12.example"));
+        assert!(glob("*a*b*", "xxaxxbxx"));
+        assert!(!glob("*a*b", "xxaxxbxx"));
+        assert!(glob("*", ""));
+        assert!(glob("", ""));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn test_stoplist() {
+        let file: Stoplist = toml::from_str(
+            r#"
+            hosts = ["nat-*.campus.example.edu"]
+            idents = ["guest*"]
+            realnames = ["Campus Guest"]
+            "#,
+        )
+        .unwrap();
+        let stoplist = Stoplist::with(&file);
+        assert!(stoplist.stops_host("nat-3.campus.example.edu"));
+        assert!(stoplist.stops_host("ealing.irccloud.com"));
+        assert!(!stoplist.stops_host("user/kks"));
+        assert!(stoplist.stops_ident("Guest42"));
+        assert!(!stoplist.stops_ident("kks"));
+        assert!(stoplist.stops_realname("campus guest"));
+        assert!(stoplist.stops_realname("Quassel IRC User"));
+        assert!(stoplist.stops_realname("https://kiwiirc.com/nextclient/"));
+        assert!(stoplist.stops_realname(" * "));
+        assert!(!stoplist.stops_realname("Roran Stronghammer"));
+        assert!(toml::from_str::<Stoplist>("nicks = []").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{VerifierBuilderError, WebPkiServerVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// How much of the server's certificate to check, after libpq's sslmodes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    /// encrypt, but take any certificate, as `prefer` and `require` do
+    #[default]
+    Nothing,
+    /// the certificate must chain to a trusted CA, as with `verify-ca`
+    Ca,
+    /// and be for the host connected to, as with `verify-full`
+    Full,
+}
+
+/// Certificate files for a TLS connection, named after libpq's `sslrootcert`,
+/// `sslcert` and `sslkey`, and how closely to check the server's.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub verify: Verify,
+    /// PEM bundle of trusted CAs; the webpki roots are used when unset
+    pub root_cert: Option<PathBuf>,
+    /// PEM client certificate chain, presented alongside `key`
+    pub cert: Option<PathBuf>,
+    /// PEM private key for `cert`
+    pub key: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    /// Builds a rustls connector that checks the server's certificate as far
+    /// as `verify` says. The handshake is always checked, so the connection
+    /// is encrypted either way.
+    pub fn connector(&self) -> Result<MakeRustlsConnect, TlsError> {
+        let mut roots = RootCertStore::empty();
+        match &self.root_cert {
+            Some(path) => {
+                for cert in certificates(path)? {
+                    roots
+                        .add(cert)
+                        .map_err(|e| TlsError::Certificate(path.clone(), e))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let builder = match self.verify {
+            Verify::Full => ClientConfig::builder().with_root_certificates(roots),
+            Verify::Ca => ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(Lenient::new(Some(roots))?)),
+            Verify::Nothing => ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(Lenient::new(None)?)),
+        };
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+                .map_err(|e| TlsError::Certificate(cert.clone(), e))?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(TlsError::Incomplete),
+        };
+        Ok(MakeRustlsConnect::new(config))
+    }
+}
+
+/// Takes certificates `verify-full` wouldn't: for other hosts when there's a
+/// `chain` to check them against, or any at all when there isn't.
+#[derive(Debug)]
+struct Lenient {
+    chain: Option<Arc<WebPkiServerVerifier>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl Lenient {
+    fn new(roots: Option<RootCertStore>) -> Result<Self, TlsError> {
+        let chain = roots
+            .map(|roots| WebPkiServerVerifier::builder(Arc::new(roots)).build())
+            .transpose()?;
+        Ok(Self {
+            chain,
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        })
+    }
+}
+
+impl ServerCertVerifier for Lenient {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(chain) = &self.chain else {
+            return Ok(ServerCertVerified::assertion());
+        };
+        match chain.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            verified => verified,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| TlsError::Read(path.into(), e))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Read(path.into(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::Empty(path.into()));
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| TlsError::Read(path.into(), e))?);
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| TlsError::Read(path.into(), e))?
+        .ok_or_else(|| TlsError::Empty(path.into()))
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("cannot read {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("no certificates or keys found in {0}")]
+    Empty(PathBuf),
+    #[error("bad certificate in {0}: {1}")]
+    Certificate(PathBuf, rustls::Error),
+    #[error("a client certificate and its key must be given together")]
+    Incomplete,
+    #[error("cannot verify certificates: {0}")]
+    Verifier(#[from] VerifierBuilderError),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tls::{TlsError, TlsFiles, Verify};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_incomplete_client_certificate() {
+        let files = TlsFiles {
+            cert: Some("client.pem".into()),
+            ..Default::default()
+        };
+        assert!(matches!(files.connector(), Err(TlsError::Incomplete)));
+    }
+
+    #[test]
+    fn test_missing_root_cert() {
+        let files = TlsFiles {
+            root_cert: Some("/nonexistent/ca.pem".into()),
+            ..Default::default()
+        };
+        assert!(matches!(files.connector(), Err(TlsError::Read(..))));
+    }
+
+    /// Run against a local postgres with `ssl = on` and a certificate for
+    /// `localhost` signed by a private CA:
+    ///
+    /// ```sh
+    /// IDENTITY_TRAVERSE_TEST_TLS_DATABASE="host=localhost user=quassel dbname=quassel sslmode=require" \
+    /// IDENTITY_TRAVERSE_TEST_TLS_ROOT_CERT=/path/to/ca.pem \
+    /// cargo test -- --ignored test_self_signed
+    /// ```
+    #[tokio::test]
+    #[ignore = "requires IDENTITY_TRAVERSE_TEST_TLS_DATABASE and IDENTITY_TRAVERSE_TEST_TLS_ROOT_CERT"]
+    async fn test_self_signed() {
+        let config: tokio_postgres::Config = std::env::var("IDENTITY_TRAVERSE_TEST_TLS_DATABASE")
+            .unwrap()
+            .parse()
+            .unwrap();
+        let root_cert =
+            PathBuf::from(std::env::var("IDENTITY_TRAVERSE_TEST_TLS_ROOT_CERT").unwrap());
+        let connects = |config: tokio_postgres::Config, files: TlsFiles| async move {
+            let Ok((client, connection)) = config.connect(files.connector().unwrap()).await else {
+                return false;
+            };
+            tokio::spawn(connection);
+            let row = client
+                .query_one(
+                    "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()",
+                    &[],
+                )
+                .await
+                .unwrap();
+            row.get::<_, bool>(0)
+        };
+        let files = |verify, root_cert: Option<&PathBuf>| TlsFiles {
+            verify,
+            root_cert: root_cert.cloned(),
+            ..Default::default()
+        };
+
+        // require takes the certificate as it comes, but still encrypts
+        assert!(connects(config.clone(), files(Verify::Nothing, None)).await);
+        // the webpki roots don't know our CA
+        assert!(!connects(config.clone(), files(Verify::Ca, None)).await);
+        assert!(!connects(config.clone(), files(Verify::Full, None)).await);
+        assert!(connects(config.clone(), files(Verify::Full, Some(&root_cert))).await);
+
+        // the certificate is for localhost, not its address
+        let mut by_address = tokio_postgres::Config::new();
+        by_address
+            .host("127.0.0.1")
+            .user(config.get_user().unwrap())
+            .dbname(config.get_dbname().unwrap())
+            .ssl_mode(config.get_ssl_mode());
+        for &port in config.get_ports() {
+            by_address.port(port);
+        }
+        if let Some(password) = config.get_password() {
+            by_address.password(password);
+        }
+        assert!(connects(by_address.clone(), files(Verify::Ca, Some(&root_cert))).await);
+        assert!(!connects(by_address, files(Verify::Full, Some(&root_cert))).await);
+    }
+}
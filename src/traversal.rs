@@ -0,0 +1,1058 @@
+use crate::graph::{Edge, Graph, NodeId, Suppressed, Suppression};
+use crate::hostmask::{HostMask, MatchOptions, Query};
+use crate::source::SenderSource;
+use crate::stoplist::Stoplist;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::hash::Hash;
+use std::ops::ControlFlow;
+use std::time::SystemTime;
+
+/// A sender row from the core, or the seed mask we started from.
+#[derive(Debug, Eq, Hash, PartialEq, Clone)]
+pub struct Sender {
+    /// `None` for the seed, which may not exist in the database at all
+    pub id: Option<i64>,
+    pub sender: HostMask,
+    pub realname: Option<String>,
+    /// the avatar's URL, on cores new enough to keep them
+    pub avatar: Option<String>,
+    /// `None` when the source doesn't know, or the sender never said anything
+    pub activity: Option<Activity>,
+    /// the networks the backlog has the sender on, sorted
+    pub networks: Vec<String>,
+    /// the channels the backlog has the sender in, sorted
+    pub channels: Vec<Channel>,
+}
+
+impl Sender {
+    pub fn seed(mask: HostMask) -> Self {
+        Self {
+            id: None,
+            sender: mask,
+            realname: None,
+            avatar: None,
+            activity: None,
+            networks: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// A sender as a source hands it back, with nothing known but its mask.
+    #[cfg(test)]
+    pub fn stored(id: i64, mask: &str) -> Self {
+        Self {
+            id: Some(id),
+            ..Self::seed(mask.parse().unwrap())
+        }
+    }
+
+    /// Whether both senders were only ever seen on different networks.
+    pub fn across_networks(&self, other: &Sender) -> bool {
+        !self.networks.is_empty()
+            && !other.networks.is_empty()
+            && !self
+                .networks
+                .iter()
+                .any(|network| other.networks.contains(network))
+    }
+
+    /// Whether the sender was seen on any of `networks`, ignoring case.
+    pub fn on_any(&self, networks: &[String]) -> bool {
+        self.networks
+            .iter()
+            .any(|network| networks.iter().any(|n| n.eq_ignore_ascii_case(network)))
+    }
+
+    /// How many channels both senders were seen in.
+    pub fn shared_channels(&self, other: &Sender) -> usize {
+        self.channels
+            .iter()
+            .filter(|channel| other.channels.binary_search(channel).is_ok())
+            .count()
+    }
+}
+
+/// A channel on one of the core's networks.
+#[derive(Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Clone)]
+pub struct Channel {
+    pub network: String,
+    pub name: String,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.name)
+    }
+}
+
+/// When a sender was around, going by the backlog.
+#[derive(Debug, Eq, Hash, PartialEq, Clone, Copy)]
+pub struct Activity {
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub messages: i64,
+}
+
+/// A nick change the backlog saw, into or out of the sender asked about.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct NickChange {
+    /// the sender on the other side of the change
+    pub sender: Sender,
+    pub observed: SystemTime,
+}
+
+/// The stretch of time senders must have been active in to be followed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub since: Option<SystemTime>,
+    /// exclusive
+    pub until: Option<SystemTime>,
+}
+
+impl Window {
+    pub fn is_open(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Whether any of `activity` falls inside the window; never, without any.
+    pub fn contains(&self, activity: Option<&Activity>) -> bool {
+        if self.is_open() {
+            return true;
+        }
+        activity.is_some_and(|activity| {
+            self.since.is_none_or(|since| activity.last_seen >= since)
+                && self.until.is_none_or(|until| activity.first_seen < until)
+        })
+    }
+}
+
+type BoxedQuery = Box<dyn Query + Send + Sync>;
+
+/// The dimension of links made by [`Traversal::follow_nick_changes`].
+pub const NICK_CHANGE: &str = "nick change";
+
+/// A sender turned up by a query, on its way into the graph.
+struct Hit {
+    parent: NodeId,
+    sender: Sender,
+    dimension: &'static str,
+    pattern: String,
+    weight: f32,
+    observed: Option<SystemTime>,
+    /// turned up by a term over the fan-out limit, which isn't pivoted through
+    hub: bool,
+}
+
+/// When to stop expanding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// iterations to run; `None` runs until no new senders turn up
+    pub depth: Option<usize>,
+    pub max_senders: usize,
+    pub max_queries: usize,
+    /// a term turning up more senders than this is a hub, and isn't pivoted through
+    pub max_fanout: usize,
+}
+
+/// Breadth first expansion from a seed over a set of pivot dimensions.
+///
+/// Every dimension turns discovered senders into query terms. Each iteration
+/// queries the terms nobody has queried yet, and whatever new senders come
+/// back feed the next iteration's frontiers. Each hit is recorded as an edge
+/// from the sender the term came from, so results can explain themselves.
+/// Terms on the stoplist are recorded in the graph as suppressed instead, as
+/// are hubs that turn up too many senders; what a hub found is kept, but only
+/// followed further if something else leads there too.
+pub struct Traversal {
+    dimensions: Vec<Box<dyn Dimension>>,
+    limits: Limits,
+    stoplist: Stoplist,
+    window: Window,
+    /// only follow senders seen on these networks; empty for any
+    networks: Vec<String>,
+    weigh_channels: bool,
+    /// senders whose nick changes are yet to be looked up, when following them
+    nick_changes: Option<Vec<NodeId>>,
+    /// senders only hubs have led to so far, and so not yet followed
+    stranded: HashSet<NodeId>,
+    graph: Graph,
+    iteration: usize,
+    queries: usize,
+}
+
+impl Traversal {
+    pub fn new(seed: Sender, limits: Limits, stoplist: Stoplist) -> Self {
+        Self {
+            dimensions: Vec::new(),
+            limits,
+            stoplist,
+            window: Window::default(),
+            networks: Vec::new(),
+            weigh_channels: false,
+            nick_changes: None,
+            stranded: HashSet::new(),
+            graph: Graph::new(seed),
+            iteration: 0,
+            queries: 0,
+        }
+    }
+
+    /// Adds a dimension whose query terms are read off each sender by `terms`
+    /// and matched according to `options`.
+    pub fn pivot<Q, F>(mut self, name: &'static str, options: MatchOptions, terms: F) -> Self
+    where
+        Q: Query + Clone + Eq + Hash + Send + Sync + 'static,
+        F: Fn(&Sender) -> Vec<Q> + Send + Sync + 'static,
+    {
+        let mut dimension = Pivot {
+            name,
+            options,
+            terms,
+            frontier: HashMap::new(),
+            visited: HashSet::new(),
+        };
+        let stopped = dimension.extend(Graph::SEED, self.graph.sender(Graph::SEED), &self.stoplist);
+        self.suppress(name, stopped);
+        self.dimensions.push(Box::new(dimension));
+        self
+    }
+
+    /// Leaves out senders who weren't active during `window`, so they don't
+    /// join the graph or lend it their terms. The seed is always kept.
+    pub fn within(mut self, window: Window) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Leaves out senders the backlog hasn't seen on any of `networks`, like
+    /// [`Traversal::within`].
+    pub fn on_networks(mut self, networks: Vec<String>) -> Self {
+        self.networks = networks;
+        self
+    }
+
+    /// Halves the weight of links between senders who were both seen in
+    /// channels, but never in the same one.
+    pub fn weigh_channels(mut self) -> Self {
+        self.weigh_channels = true;
+        self
+    }
+
+    /// Also links every sender to those the backlog saw it change nick into
+    /// or out of. Unlike pivots these are certain, so they link at full weight.
+    pub fn follow_nick_changes(mut self) -> Self {
+        self.nick_changes = Some(vec![Graph::SEED]);
+        self
+    }
+
+    /// Queries the frontier sizes add up to.
+    fn planned(&self) -> usize {
+        self.dimensions.iter().map(|d| d.len()).sum::<usize>()
+            + self.nick_changes.as_ref().map_or(0, Vec::len)
+    }
+
+    fn suppress(&mut self, dimension: &'static str, terms: Vec<String>) {
+        for term in terms {
+            info!("{term} is on the stoplist; not following its {dimension}");
+            self.graph.suppress(Suppressed {
+                dimension,
+                term,
+                reason: Suppression::Stoplist,
+            });
+        }
+    }
+
+    pub fn into_graph(self) -> Graph {
+        self.graph
+    }
+
+    /// Runs one iteration, returning the newly discovered senders, or `None`
+    /// once the traversal is finished.
+    pub async fn step(&mut self, source: &impl SenderSource) -> Option<Vec<NodeId>> {
+        let i = self.iteration;
+        if self.limits.depth.is_some_and(|depth| i >= depth) {
+            info!("reached depth {i}; ending");
+            return None;
+        }
+        let planned = self.planned();
+        if planned == 0 {
+            info!("no new query terms found; ending");
+            return None;
+        }
+        if self.queries + planned > self.limits.max_queries {
+            warn!(
+                "iteration {i} needs {planned} more queries, over the limit of {}; ending",
+                self.limits.max_queries
+            );
+            return None;
+        }
+        if self.graph.len() >= self.limits.max_senders {
+            warn!(
+                "found {} senders, over the limit of {}; ending",
+                self.graph.len(),
+                self.limits.max_senders
+            );
+            return None;
+        }
+        debug!(
+            "starting iteration {i}; {}",
+            self.dimensions
+                .iter()
+                .map(|d| format!("{} {}", d.len(), d.name()))
+                .chain(
+                    self.nick_changes
+                        .as_ref()
+                        .map(|nodes| format!("{} {NICK_CHANGE}", nodes.len()))
+                )
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        self.iteration += 1;
+        self.queries += planned;
+        let depth = self.iteration;
+
+        let mut hits = Vec::new();
+        for dimension in &mut self.dimensions {
+            for (query, parent) in dimension.drain() {
+                let options = MatchOptions {
+                    literal: parent != Graph::SEED,
+                    ..*dimension.options()
+                };
+                let pattern = query.query(&options).to_string();
+                debug!("querying for {} {pattern}", dimension.name());
+                let matching = |senders: Vec<Sender>| {
+                    senders
+                        .into_iter()
+                        .filter(|sender| {
+                            let matches = query.matches(&sender.sender, &options);
+                            if !matches {
+                                debug!("{pattern} matched {} only by pattern", sender.sender);
+                            }
+                            matches
+                        })
+                        .collect::<Vec<_>>()
+                };
+                // one past the fan-out limit is enough to tell a hub
+                let limit = self.limits.max_fanout.saturating_add(1);
+                let senders = match source.lookup(&*query, &options, Some(limit)).await {
+                    Ok(senders) if senders.len() >= limit => {
+                        let senders = matching(senders);
+                        if senders.len() > self.limits.max_fanout {
+                            Ok(senders)
+                        } else {
+                            // senders that only matched the pattern took
+                            // up the limit, so some real ones may be missing
+                            debug!("{pattern} reached the limit by pattern; asking for all");
+                            source.lookup(&*query, &options, None).await.map(matching)
+                        }
+                    }
+                    senders => senders.map(matching),
+                };
+                match senders {
+                    Ok(senders) => {
+                        let hub = senders.len() > self.limits.max_fanout;
+                        if hub {
+                            warn!(
+                                "{} {query} turned up over {} senders, the fan-out limit; \
+                                 not pivoting through it",
+                                dimension.name(),
+                                self.limits.max_fanout
+                            );
+                            self.graph.suppress(Suppressed {
+                                dimension: dimension.name(),
+                                term: query.to_string(),
+                                reason: Suppression::Hub(self.limits.max_fanout),
+                            });
+                        }
+                        hits.extend(senders.into_iter().map(|sender| Hit {
+                            parent,
+                            weight: query.weight(&sender.sender),
+                            sender,
+                            dimension: dimension.name(),
+                            pattern: pattern.clone(),
+                            observed: None,
+                            hub,
+                        }));
+                    }
+                    Err(e) => warn!("{} query {pattern} failed: {e}", dimension.name()),
+                }
+            }
+        }
+        for node in self
+            .nick_changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+        {
+            let from = self.graph.sender(node);
+            debug!("looking up nick changes of {}", from.sender);
+            match source.nick_changes(from).await {
+                Ok(changes) => hits.extend(changes.into_iter().map(|change| Hit {
+                    parent: node,
+                    sender: change.sender,
+                    dimension: NICK_CHANGE,
+                    pattern: from.sender.to_string(),
+                    weight: 1.0,
+                    observed: Some(change.observed),
+                    hub: false,
+                })),
+                Err(e) => warn!("{NICK_CHANGE} lookup of {} failed: {e}", from.sender),
+            }
+        }
+
+        let mut found = Vec::new();
+        // the senders whose terms are queued for the next iteration
+        let mut frontier = Vec::new();
+        let mut pruned = HashSet::new();
+        let mut truncated = HashSet::new();
+        let mut counts = HashMap::<&str, usize>::new();
+        for hit in hits {
+            let sender = hit.sender;
+            if !self.window.contains(sender.activity.as_ref()) {
+                debug!("{} wasn't active in the window; pruning", sender.sender);
+                pruned.insert(sender.sender);
+                continue;
+            }
+            if !self.networks.is_empty() && !sender.on_any(&self.networks) {
+                debug!("{} wasn't on the networks; pruning", sender.sender);
+                pruned.insert(sender.sender);
+                continue;
+            }
+            if self.graph.len() >= self.limits.max_senders && self.graph.find(&sender).is_none() {
+                debug!("{} is over the sender limit; dropping", sender.sender);
+                truncated.insert(sender.sender);
+                continue;
+            }
+            let mut weight = hit.weight;
+            let from = self.graph.sender(hit.parent);
+            // a nick change was seen happening, so nothing weakens it
+            let certain = hit.dimension == NICK_CHANGE;
+            if self.weigh_channels
+                && !certain
+                && !from.channels.is_empty()
+                && !sender.channels.is_empty()
+                && from.shared_channels(&sender) == 0
+            {
+                weight /= 2.0;
+            }
+            // the same nick on another network is often someone else
+            let cross_network = from.across_networks(&sender);
+            if cross_network && !certain {
+                weight /= 2.0;
+            }
+            let (node, new) = self.graph.insert(sender);
+            self.graph.link(Edge {
+                from: hit.parent,
+                to: node,
+                dimension: hit.dimension,
+                pattern: hit.pattern,
+                depth,
+                weight,
+                cross_network,
+                observed: hit.observed,
+            });
+            if new {
+                *counts.entry(hit.dimension).or_default() += 1;
+                found.push(node);
+            }
+            if hit.hub {
+                if new {
+                    self.stranded.insert(node);
+                }
+            } else if new || self.stranded.remove(&node) {
+                frontier.push(node);
+            }
+        }
+        for (dimension, count) in counts {
+            debug!("found {count} senders by {dimension}");
+        }
+        if !pruned.is_empty() {
+            info!(
+                "pruned {} senders from outside the window or networks",
+                pruned.len()
+            );
+        }
+        if !truncated.is_empty() {
+            warn!(
+                "reached the limit of {} senders; left out {} more",
+                self.limits.max_senders,
+                truncated.len()
+            );
+        }
+        if let Some(nodes) = &mut self.nick_changes {
+            nodes.extend(&frontier);
+        }
+        for &node in &frontier {
+            for i in 0..self.dimensions.len() {
+                let dimension = &mut self.dimensions[i];
+                let stopped = dimension.extend(node, self.graph.sender(node), &self.stoplist);
+                let name = dimension.name();
+                self.suppress(name, stopped);
+            }
+        }
+        debug!("there are {} total senders", self.graph.len());
+        Some(found)
+    }
+
+    /// Steps until finished, handing each iteration's new senders to `found`,
+    /// which may cut the traversal short.
+    pub async fn run<F>(&mut self, source: &impl SenderSource, mut found: F)
+    where
+        F: FnMut(&Graph, &[NodeId]) -> ControlFlow<()>,
+    {
+        while let Some(nodes) = self.step(source).await {
+            if found(&self.graph, &nodes).is_break() {
+                info!("stopped after iteration {}", self.iteration);
+                return;
+            }
+        }
+    }
+}
+
+/// Object safe face of a [`Pivot`], so dimensions of different term types share a list.
+trait Dimension: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn options(&self) -> &MatchOptions;
+    /// Queues the terms `sender` contributes that haven't been queried yet,
+    /// returning those the stoplist rules out.
+    fn extend(&mut self, node: NodeId, sender: &Sender, stoplist: &Stoplist) -> Vec<String>;
+    /// Takes the frontier, marking it visited, along with where each term came from.
+    fn drain(&mut self) -> Vec<(BoxedQuery, NodeId)>;
+    fn len(&self) -> usize;
+}
+
+struct Pivot<Q, F> {
+    name: &'static str,
+    options: MatchOptions,
+    terms: F,
+    /// unqueried terms, and the first sender to contribute each
+    frontier: HashMap<Q, NodeId>,
+    visited: HashSet<Q>,
+}
+
+impl<Q, F> Dimension for Pivot<Q, F>
+where
+    Q: Query + Clone + Eq + Hash + Send + Sync + 'static,
+    F: Fn(&Sender) -> Vec<Q> + Send + Sync,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn options(&self) -> &MatchOptions {
+        &self.options
+    }
+
+    fn extend(&mut self, node: NodeId, sender: &Sender, stoplist: &Stoplist) -> Vec<String> {
+        let mut stopped = Vec::new();
+        for term in (self.terms)(sender) {
+            // a bare wildcard matches every sender, so it links nothing
+            if term.is_wildcard() || self.visited.contains(&term) {
+                continue;
+            }
+            if term.stopped(stoplist) {
+                stopped.push(term.to_string());
+                // so it's only reported once
+                self.visited.insert(term);
+                continue;
+            }
+            self.frontier.entry(term).or_insert(node);
+        }
+        stopped
+    }
+
+    fn drain(&mut self) -> Vec<(BoxedQuery, NodeId)> {
+        let frontier = std::mem::take(&mut self.frontier);
+        self.visited.extend(frontier.keys().cloned());
+        frontier
+            .into_iter()
+            .map(|(term, node)| (Box::new(term) as BoxedQuery, node))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.frontier.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::{Suppressed, Suppression};
+    use crate::hostmask::{MatchOptions, Query};
+    use crate::source::SenderSource;
+    use crate::stoplist::Stoplist;
+    use crate::traversal::{
+        Activity, Channel, Limits, NickChange, Sender, Traversal, Window, NICK_CHANGE,
+    };
+    use itertools::Itertools;
+    use std::collections::HashMap;
+    use std::ops::ControlFlow;
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// Canned answers keyed by query pattern, recording what was asked.
+    struct Mock {
+        answers: HashMap<String, Vec<Sender>>,
+        /// nick changes keyed by the mask they're looked up from
+        changes: HashMap<String, Vec<NickChange>>,
+        asked: Mutex<Vec<String>>,
+    }
+
+    impl Mock {
+        fn new(answers: &[(&str, &[Sender])]) -> Self {
+            Self {
+                answers: answers
+                    .iter()
+                    .map(|(query, senders)| (query.to_string(), senders.to_vec()))
+                    .collect(),
+                changes: HashMap::new(),
+                asked: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl SenderSource for Mock {
+        type Error = String;
+
+        async fn lookup(
+            &self,
+            query: &(dyn Query + Send + Sync),
+            options: &MatchOptions,
+            limit: Option<usize>,
+        ) -> Result<Vec<Sender>, String> {
+            let query = query.query(options).to_string();
+            self.asked.lock().unwrap().push(query.clone());
+            let mut senders = self.answers.get(&query).cloned().unwrap_or_default();
+            senders.truncate(limit.unwrap_or(usize::MAX));
+            Ok(senders)
+        }
+
+        async fn nick_changes(&self, sender: &Sender) -> Result<Vec<NickChange>, String> {
+            let mask = sender.sender.to_string();
+            self.asked.lock().unwrap().push(mask.clone());
+            Ok(self.changes.get(&mask).cloned().unwrap_or_default())
+        }
+    }
+
+    fn new(seed: &Sender, depth: Option<usize>, max_queries: usize) -> Traversal {
+        let limits = Limits {
+            depth,
+            max_senders: 100,
+            max_queries,
+            max_fanout: 100,
+        };
+        with(seed, limits, Stoplist::default())
+    }
+
+    fn with(seed: &Sender, limits: Limits, stoplist: Stoplist) -> Traversal {
+        Traversal::new(Sender::seed(seed.sender.clone()), limits, stoplist)
+            .pivot("nick", MatchOptions::default(), |s: &Sender| {
+                vec![s.sender.nick().clone()]
+            })
+            .pivot("host", MatchOptions::default(), |s: &Sender| {
+                vec![s.sender.host().clone()]
+            })
+    }
+
+    #[tokio::test]
+    async fn test_traversal() {
+        let kks = Sender::stored(1, "kks!~kks@user/kks");
+        let kks_ = Sender::stored(2, "kks_!~kks@user/kks");
+        let away = Sender::stored(3, "kks|away!~kks@example.org");
+        let other = Sender::stored(4, "other!~other@example.org");
+        let mock = Mock::new(&[
+            ("kks%!%", &[kks.clone(), kks_.clone(), away.clone()]),
+            ("%@user/kks", &[kks.clone(), kks_.clone()]),
+            ("%@%.example.org", &[away.clone(), other.clone()]),
+        ]);
+
+        let mut traversal = new(&kks, None, 100);
+        let first = traversal.step(&mock).await.unwrap();
+        assert_eq!(first.len(), 3);
+        let second = traversal.step(&mock).await.unwrap();
+        assert_eq!(second.len(), 1);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+
+        let graph = traversal.into_graph();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.sender(second[0]), &other);
+        // nothing is asked twice, even though several senders share terms
+        let asked = mock.asked.lock().unwrap();
+        let mut unique = asked.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(asked.len(), unique.len());
+        assert!(asked.contains(&"other%!%".to_string()));
+
+        // other was reached through kks|away's host, which was reached by nick
+        let chain = graph.chain(second[0]);
+        assert_eq!(
+            chain
+                .iter()
+                .map(|e| (
+                    graph.sender(e.from).sender.to_string(),
+                    e.dimension,
+                    e.pattern.as_str(),
+                    e.depth
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("kks!~kks@user/kks".to_string(), "nick", "kks%!%", 1),
+                (
+                    "kks|away!~kks@example.org".to_string(),
+                    "host",
+                    "%@%.example.org",
+                    2
+                ),
+            ]
+        );
+        assert_eq!(graph.sender(chain[0].from).id, None);
+        // kks_ turned up on both the nick and host queries of the seed
+        let (kks_node, _) = graph.discovered().find(|(_, s)| **s == kks_).unwrap();
+        assert_eq!(graph.edges().iter().filter(|e| e.to == kks_node).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let kks = Sender::stored(1, "kks!~kks@user/kks");
+        let other = Sender::stored(2, "other!~other@user/kks");
+        let mock = Mock::new(&[("%@user/kks", &[kks.clone(), other.clone()])]);
+
+        let mut traversal = new(&kks, Some(1), 100);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        assert_eq!(traversal.into_graph().len(), 2);
+        assert_eq!(mock.asked.lock().unwrap().len(), 2);
+
+        let mut traversal = new(&kks, None, 1);
+        assert!(traversal.step(&mock).await.is_none());
+
+        let mut traversal = new(&kks, None, 100);
+        let mut iterations = 0;
+        traversal
+            .run(&mock, |_, _| {
+                iterations += 1;
+                ControlFlow::Break(())
+            })
+            .await;
+        assert_eq!(iterations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_suppression() {
+        let kks = Sender::stored(1, "kks!~kks@user/kks");
+        let kks_ = Sender::stored(2, "kks_!~kks@user/kks");
+        let away = Sender::stored(3, "kks|away!~kks@example.org");
+        let unseen = Sender::stored(4, "kks^!~kks@example.net");
+        let mock = Mock::new(&[
+            ("kks%!%", &[kks.clone(), kks_.clone(), away.clone(), unseen]),
+            ("%@user/kks", &[kks.clone(), kks_.clone()]),
+        ]);
+        let limits = Limits {
+            depth: None,
+            max_senders: 100,
+            max_queries: 100,
+            max_fanout: 2,
+        };
+
+        // the nick turns up too many senders to be worth following, so only
+        // one past the limit are fetched, and kept without pivoting through
+        let mut traversal = with(&kks, limits, Stoplist::default());
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        assert_eq!(graph.len(), 3);
+        let (node, _) = graph
+            .discovered()
+            .find(|(_, sender)| sender.id == Some(3))
+            .unwrap();
+        assert_eq!(graph.found_by(node).unwrap().dimension, "nick");
+        assert!(!mock
+            .asked
+            .lock()
+            .unwrap()
+            .contains(&"%@%.example.org".to_string()));
+        assert_eq!(
+            graph.suppressed(),
+            [Suppressed {
+                dimension: "nick",
+                term: "kks".to_string(),
+                reason: Suppression::Hub(2),
+            }]
+        );
+
+        // and a stoplisted host is never even asked about
+        let stoplist = Stoplist {
+            hosts: vec!["user/*".to_string()],
+            ..Default::default()
+        };
+        let mock = Mock::new(&[("%@user/kks", std::slice::from_ref(&kks))]);
+        let mut traversal = with(&kks, limits, stoplist);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        assert_eq!(*mock.asked.lock().unwrap(), ["kks%!%"]);
+        let graph = traversal.into_graph();
+        assert!(graph.is_empty());
+        assert_eq!(graph.suppressed()[0].reason, Suppression::Stoplist);
+        assert_eq!(graph.suppressed()[0].term, "user/kks");
+    }
+
+    #[tokio::test]
+    async fn test_max_senders() {
+        let kks = Sender::stored(1, "kks!~kks@user/kks");
+        let kks_ = Sender::stored(2, "kks_!~kks@user/kks");
+        let away = Sender::stored(3, "kks|away!~kks@example.org");
+        let mock = Mock::new(&[
+            ("kks%!%", &[kks.clone(), kks_.clone(), away.clone()]),
+            ("%@user/kks", &[kks.clone(), kks_.clone()]),
+        ]);
+        let limits = Limits {
+            depth: None,
+            max_senders: 2,
+            max_queries: 100,
+            max_fanout: 100,
+        };
+        let mut traversal = with(&kks, limits, Stoplist::default());
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        // the first iteration alone turns up three, but only two are kept,
+        // still linked by every dimension that found them
+        assert_eq!(graph.len(), 2);
+        assert!(graph.discovered().all(|(_, sender)| sender.id != Some(3)));
+        assert_eq!(graph.edges().len(), 4);
+    }
+
+    /// Active for a day from `day` days after the epoch.
+    fn active(day: u64) -> Option<Activity> {
+        let first_seen = UNIX_EPOCH + Duration::from_secs(day * 86400);
+        Some(Activity {
+            first_seen,
+            last_seen: first_seen + Duration::from_secs(86399),
+            messages: 1,
+        })
+    }
+
+    fn day(day: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(day * 86400))
+    }
+
+    #[tokio::test]
+    async fn test_window() {
+        let window = Window {
+            since: day(10),
+            until: day(20),
+        };
+        assert!(Window::default().contains(None));
+        assert!(!window.contains(None));
+        assert!(window.contains(active(10).as_ref()));
+        assert!(window.contains(active(19).as_ref()));
+        assert!(!window.contains(active(9).as_ref()));
+        assert!(!window.contains(active(20).as_ref()));
+        let long = Some(Activity {
+            last_seen: day(30).unwrap(),
+            ..active(5).unwrap()
+        });
+        assert!(window.contains(long.as_ref()));
+
+        let kks = Sender::stored(1, "kks!~kks@user/kks");
+        let old = Sender {
+            activity: active(5),
+            ..Sender::stored(2, "kks_!~kks@example.org")
+        };
+        let recent = Sender {
+            activity: active(15),
+            ..Sender::stored(3, "kks|away!~kks@example.net")
+        };
+        let mock = Mock::new(&[("kks%!%", &[old.clone(), recent.clone()])]);
+        let mut traversal = new(&kks, None, 100).within(window);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        assert_eq!(
+            graph.discovered().map(|(_, s)| s).collect::<Vec<_>>(),
+            [&recent]
+        );
+        // the pruned sender's host was never asked after
+        assert!(!mock
+            .asked
+            .lock()
+            .unwrap()
+            .contains(&"%@%.example.org".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_weigh_channels() {
+        let channels = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| Channel {
+                    network: "libera".to_string(),
+                    name: name.to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let seed = Sender::stored(1, "kks!~kks@seed.example");
+        let kks = Sender {
+            channels: channels(&["#a", "#b"]),
+            ..Sender::stored(2, "kks_!~kks@a.example")
+        };
+        let stranger = Sender {
+            channels: channels(&["#c"]),
+            ..Sender::stored(3, "bob!~b@a.example")
+        };
+        let friend = Sender {
+            channels: channels(&["#b"]),
+            ..Sender::stored(4, "carl!~c@a.example")
+        };
+        let quiet = Sender::stored(5, "dan!~d@a.example");
+        assert_eq!(kks.shared_channels(&friend), 1);
+        assert_eq!(kks.shared_channels(&stranger), 0);
+        let mock = Mock::new(&[
+            ("kks%!%", std::slice::from_ref(&kks)),
+            (
+                "%@%.a.example",
+                &[kks.clone(), stranger.clone(), friend.clone(), quiet.clone()],
+            ),
+        ]);
+        let weights = |traversal: Traversal| {
+            let graph = traversal.into_graph();
+            graph
+                .edges()
+                .iter()
+                .filter(|edge| edge.dimension == "host")
+                .map(|edge| (graph.sender(edge.to).id.unwrap(), edge.weight))
+                .sorted_by_key(|&(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+        let mut traversal = new(&seed, Some(2), 100).weigh_channels();
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        // only those seen in channels, and none of the same ones, are weakened
+        assert_eq!(weights(traversal), [(3, 0.5), (4, 1.0), (5, 1.0)]);
+        let mut traversal = new(&seed, Some(2), 100);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        assert!(weights(traversal).iter().all(|&(_, weight)| weight == 1.0));
+    }
+
+    #[tokio::test]
+    async fn test_networks() {
+        let on = |network: &str, sender: Sender| Sender {
+            networks: vec![network.to_string()],
+            ..sender
+        };
+        let seed = on("libera", Sender::stored(1, "kks!~kks@seed.example"));
+        let kks = on("libera", Sender::stored(2, "kks_!~kks@a.example"));
+        let away = on("oftc", Sender::stored(3, "bob!~b@a.example"));
+        let unseen = Sender::stored(4, "carl!~c@a.example");
+        assert!(seed.across_networks(&away));
+        assert!(!seed.across_networks(&kks));
+        assert!(!seed.across_networks(&unseen));
+        let mock = Mock::new(&[
+            ("kks%!%", std::slice::from_ref(&kks)),
+            (
+                "%@%.a.example",
+                &[kks.clone(), away.clone(), unseen.clone()],
+            ),
+        ]);
+        let mut traversal = new(&seed, Some(2), 100);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        let links = graph
+            .edges()
+            .iter()
+            .filter(|edge| edge.dimension == "host")
+            .map(|edge| {
+                let id = graph.sender(edge.to).id.unwrap();
+                (id, edge.weight, edge.cross_network)
+            })
+            .sorted_by_key(|&(id, _, _)| id)
+            .collect::<Vec<_>>();
+        // only a link between two known, disjoint sets of networks is weakened
+        assert_eq!(links, [(3, 0.5, true), (4, 1.0, false)]);
+
+        let mut traversal = new(&seed, Some(2), 100).on_networks(vec!["LIBERA".to_string()]);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        let ids = graph
+            .discovered()
+            .filter_map(|(_, s)| s.id)
+            .collect::<Vec<_>>();
+        // neither the sender on oftc nor the one seen nowhere is kept
+        assert_eq!(ids, [2]);
+    }
+
+    #[tokio::test]
+    async fn test_nick_changes() {
+        // in no channels or networks in common, which would weaken other links
+        let on = |network: &str, channel: &str, sender: Sender| Sender {
+            networks: vec![network.to_string()],
+            channels: vec![Channel {
+                network: network.to_string(),
+                name: channel.to_string(),
+            }],
+            ..sender
+        };
+        let seed = on("libera", "#a", Sender::stored(1, "kks!~kks@user/kks"));
+        let renamed = on("libera", "#b", Sender::stored(2, "sks!~sks@sks.example"));
+        let older = on("oftc", "#c", Sender::stored(3, "kay!~sks@sks.example"));
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let change = |sender: &Sender, secs| NickChange {
+            sender: sender.clone(),
+            observed: at(secs),
+        };
+        let mock = Mock {
+            changes: HashMap::from([
+                ("kks!~kks@user/kks".to_string(), vec![change(&renamed, 200)]),
+                (
+                    "sks!~sks@sks.example".to_string(),
+                    vec![change(&seed, 200), change(&older, 100)],
+                ),
+            ]),
+            ..Mock::new(&[])
+        };
+        let mut traversal = new(&seed, None, 100).weigh_channels().follow_nick_changes();
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        let graph = traversal.into_graph();
+        let (kay, _) = graph
+            .discovered()
+            .find(|(_, sender)| sender.id == Some(3))
+            .unwrap();
+        // forward into the new nick, then back out to the one before it
+        let chain = graph
+            .chain(kay)
+            .iter()
+            .map(|edge| {
+                let to = graph.sender(edge.to).sender.to_string();
+                (edge.dimension, to, edge.depth, edge.observed)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chain,
+            [
+                (
+                    NICK_CHANGE,
+                    "sks!~sks@sks.example".to_string(),
+                    1,
+                    Some(at(200))
+                ),
+                (
+                    NICK_CHANGE,
+                    "kay!~sks@sks.example".to_string(),
+                    2,
+                    Some(at(100))
+                ),
+            ]
+        );
+        // certain, so never weakened
+        assert!(graph.edges().iter().all(|edge| edge.weight == 1.0));
+        let asked = mock.asked.lock().unwrap().clone();
+        assert_eq!(
+            asked
+                .iter()
+                .filter(|&mask| mask == "sks!~sks@sks.example")
+                .count(),
+            1
+        );
+
+        let mut traversal = new(&seed, None, 100);
+        traversal.run(&mock, |_, _| ControlFlow::Continue(())).await;
+        assert!(traversal.into_graph().is_empty());
+    }
+}
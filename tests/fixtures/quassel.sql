@@ -0,0 +1,110 @@
+-- A trimmed down copy of Quassel's PostgreSQL schema with a handful of rows.
+-- Only the columns that identity-traverse reads are kept.
+
+CREATE TABLE quasseluser (
+    userid serial PRIMARY KEY,
+    username varchar(64) UNIQUE NOT NULL,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE network (
+    networkid serial PRIMARY KEY,
+    userid integer NOT NULL REFERENCES quasseluser (userid) ON DELETE CASCADE,
+    networkname varchar(32) NOT NULL,
+    UNIQUE (userid, networkname)
+);
+
+CREATE TABLE buffer (
+    bufferid serial PRIMARY KEY,
+    userid integer NOT NULL REFERENCES quasseluser (userid) ON DELETE CASCADE,
+    networkid integer NOT NULL REFERENCES network (networkid) ON DELETE CASCADE,
+    buffername varchar(128) NOT NULL,
+    buffercname varchar(128) NOT NULL,
+    buffertype integer NOT NULL DEFAULT 0,
+    UNIQUE (userid, networkid, buffercname)
+);
+
+CREATE TABLE sender (
+    senderid bigserial PRIMARY KEY,
+    sender varchar(128) NOT NULL,
+    realname TEXT,
+    avatarurl TEXT
+);
+
+CREATE TABLE backlog (
+    messageid bigserial PRIMARY KEY,
+    time timestamp NOT NULL,
+    bufferid integer NOT NULL REFERENCES buffer (bufferid) ON DELETE CASCADE,
+    type integer NOT NULL,
+    flags integer NOT NULL,
+    senderid bigint NOT NULL REFERENCES sender (senderid),
+    senderprefixes varchar(4),
+    message TEXT
+);
+
+CREATE TABLE coreinfo (
+    key TEXT PRIMARY KEY,
+    value TEXT
+);
+
+INSERT INTO coreinfo (key, value) VALUES ('schemaversion', '31');
+
+INSERT INTO quasseluser (userid, username, password) VALUES
+    (1, 'alice', ''),
+    (2, 'bob', '');
+
+INSERT INTO network (networkid, userid, networkname) VALUES
+    (1, 1, 'libera'),
+    (2, 1, 'oftc'),
+    (3, 2, 'libera');
+
+-- buffertype: 1 status, 2 channel, 4 query
+INSERT INTO buffer (bufferid, userid, networkid, buffername, buffercname, buffertype) VALUES
+    (1, 1, 1, '#quassel', '#quassel', 2),
+    (2, 1, 1, '#rust', '#rust', 2),
+    (3, 1, 2, '#oftc', '#oftc', 2),
+    (4, 2, 3, '#quassel', '#quassel', 2);
+
+INSERT INTO sender (senderid, sender, realname, avatarurl) VALUES
+    (1, 'kks!~kks@user/kks', 'kks', NULL),
+    (2, 'kks_!~kks@user/kks', 'kks', NULL),
+    (3, 'kks|away!~kks@66.205.192.51', 'kks', NULL),
+    (4, 'roran!~roran@66.205.192.77', 'Roran Stronghammer', NULL),
+    (5, 'roran!~roran@user/roran', 'Roran Stronghammer', 'https://example.com/roran.png'),
+    (6, 'Unit640!~Unit640@user/Unit640', 'unit', NULL),
+    (7, 'foo_bar!~foo@gateway/web/irccloud.com/x-abc', 'foo', NULL),
+    (8, 'fooXbar!~foo@example.org', 'foo', NULL),
+    (9, 'kks!~kks@oftc.example.net', 'kks', NULL),
+    (10, 'Unit640!~Unit640@gateway/shell/example.org', 'unit', NULL),
+    (11, 'stranger!~stranger@gateway/shell/example.org', 'stranger', NULL),
+    (12, 'webkks!~42cdc033@gateway/web/cgi-irc/kiwiirc.com/session', 'kks', NULL),
+    (13, 'lounge!~lounge@gateway/web/thelounge/ip.66.205.192.51', 'lounge', NULL),
+    (14, 'back\slash!~bs@escapes.example', 'bs', NULL),
+    (15, 'backslash!~bs@escapes.example', 'bs', NULL),
+    (16, '50%off!~pct@escapes.example', 'pct', NULL),
+    (17, '50xoff!~pct@escapes.example', 'pct', NULL),
+    (18, 'KKS[m]!~kks@gateway/shell/matrix.org/x-kks', 'kks', NULL),
+    (19, 'alice!~user@alice.example', 'alice', NULL),
+    (20, 'bob!~user@bob.example', 'bob', NULL),
+    (21, 'cloudy!~cloudy@ealing.irccloud.com', 'cloudy', NULL),
+    (22, 'rainy!~rainy@ealing.irccloud.com', 'rainy', NULL),
+    (23, 'sleeper!~zz@sleeper.example', 'kks', NULL),
+    (24, 'rs!~rs@rs.example', 'roran stronghammer', NULL),
+    (25, 'newbie!~newbie@newbie.example', 'Quassel IRC User', NULL),
+    (26, 'novice!~novice@novice.example', 'Quassel IRC User', NULL),
+    (27, 'oldkks!~old@unaffiliated/KKS', 'old', NULL),
+    (28, 'hidden!~hidden@gateway/tor-sasl/kks', 'hidden', NULL),
+    (29, 'hammer!~hammer@hammer.example', 'hammer', 'https://example.com/roran.png');
+
+-- type: 1 plain, 8 nick, 32 join
+INSERT INTO backlog (messageid, time, bufferid, type, flags, senderid, message) VALUES
+    (1, '2015-03-01 12:00:00', 1, 32, 0, 1, '#quassel'),
+    (2, '2015-03-01 12:01:00', 1, 1, 0, 1, 'hello'),
+    (3, '2015-03-01 12:05:00', 1, 8, 0, 1, 'kks_'),
+    (4, '2015-03-01 12:06:00', 1, 1, 0, 2, 'still me'),
+    (5, '2024-06-01 08:00:00', 2, 1, 0, 3, 'back again'),
+    (6, '2024-06-02 09:00:00', 1, 1, 0, 4, 'hi'),
+    (7, '2024-06-02 09:10:00', 2, 1, 0, 5, 'hi again'),
+    (8, '2020-01-01 00:00:00', 1, 1, 0, 6, 'unit here'),
+    (9, '2023-01-01 00:00:00', 3, 1, 0, 9, 'oftc kks'),
+    (10, '2024-06-03 10:00:00', 4, 1, 0, 4, 'bob sees roran');